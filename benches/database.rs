@@ -0,0 +1,156 @@
+//! Criterion benchmarks calling the `Database` methods directly, without HTTP,
+//! so the storage layer's share of a request can be told apart from axum and
+//! serialization (compare with the `bench` binary's numbers).
+//!
+//! The in-memory and SQLite backends always run. Networked backends only run
+//! when the env var holding their URL is set, like the conformance tests, e.g.
+//! `POSTGRES_URL=postgresql://... cargo bench --bench database postgres`.
+//! Every backend is seeded with `RECORDS` users first, replacing its existing
+//! users like the `seed` command.
+
+use anyhow::Context;
+use clap::Parser;
+use criterion::{Criterion, criterion_group, criterion_main};
+use diesel_sqlite_benchmark::config::{Args, Config, ExecutorMode};
+use diesel_sqlite_benchmark::database::{BatchUpdate, CreateUser, Database, ListUsers, UpdateUser, UserCursor};
+use diesel_sqlite_benchmark::databases::*;
+use diesel_sqlite_benchmark::err::ServerError;
+use diesel_sqlite_benchmark::seed::{self, SeedArgs};
+use tokio::runtime::Runtime;
+
+/// Users seeded into each backend, read and updated round-robin.
+const RECORDS: u64 = 10_000;
+
+/// Users per call of the batch benchmarks.
+const BATCH_SIZE: u64 = 100;
+
+/// The server's configuration from env vars and `CONFIG_FILE`, with SQLite on a
+/// temporary file rather than `:memory:`, which is limited to one connection.
+fn config() -> Config {
+    let args = Args::try_parse_from(["database"]).expect("Invalid configuration in environment");
+    let mut config = Config::from_args(args).expect("Invalid configuration in environment");
+    config.sqlite.path = std::env::temp_dir().join("criterion_database.db").to_string_lossy().into_owned();
+    config
+}
+
+fn url_set(var: &str) -> bool {
+    let set = std::env::var(var).is_ok();
+    if !set {
+        eprintln!("skipping the backends using {}, which is not set", var);
+    }
+    set
+}
+
+fn bench_backend<T: Database>(c: &mut Criterion, name: &str, config: &Config) {
+    let runtime = Runtime::new().expect("Failed to start tokio runtime");
+    let db = runtime
+        .block_on(async {
+            let db = T::init(config).await.context("Failed to initialize database")?;
+            seed::seed(&db, &SeedArgs { users: RECORDS, start: 1, batch_size: 10_000 }).await?;
+            anyhow::Ok(db)
+        })
+        .unwrap_or_else(|e| panic!("Failed to set up {}: {:#}", name, e));
+    let db = &db;
+    let mut group = c.benchmark_group(name);
+
+    let mut n = 0;
+    group.bench_function("get_user", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let username = format!("user{}", n % RECORDS + 1);
+            async move { db.get_user(username).await.map_err(Into::<ServerError>::into).unwrap() }
+        })
+    });
+    group.bench_function("get_user_not_found", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let result = db.get_user("missing".to_string()).await.map_err(Into::<ServerError>::into);
+            assert!(matches!(result, Err(ServerError::NotFound(_))));
+        })
+    });
+    group.bench_function("update_user", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let username = format!("user{}", n % RECORDS + 1);
+            let update = UpdateUser::with_age((n % 100) as u32);
+            async move { db.update_user(username, update, None).await.map_err(Into::<ServerError>::into).unwrap() }
+        })
+    });
+    // A page of `GET /users`, seeking to a different username each time
+    group.bench_function("list_users", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let cursor = UserCursor::Username(Some(format!("user{}", n % RECORDS + 1)));
+            async move { db.list_users(ListUsers { cursor, limit: 100 }).await.map_err(Into::<ServerError>::into).unwrap() }
+        })
+    });
+    // Deleting right away keeps the table at `RECORDS` users however many
+    // iterations criterion picks
+    group.bench_function("create_and_delete_user", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let username = format!("criterion{}", n);
+            async move {
+                db.create_user(CreateUser { username: username.clone() }).await.map_err(Into::<ServerError>::into).unwrap();
+                db.delete_user(username, None).await.map_err(Into::<ServerError>::into).unwrap();
+            }
+        })
+    });
+    // The batches cover the same users as `BATCH_SIZE` calls of the benchmarks above
+    let batch = |n: u64| (0..BATCH_SIZE).map(move |i| format!("user{}", (n * BATCH_SIZE + i) % RECORDS + 1));
+    group.bench_function("get_users", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let usernames = batch(n).collect();
+            async move { db.get_users(usernames).await.map_err(Into::<ServerError>::into).unwrap() }
+        })
+    });
+    group.bench_function("update_users", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let update = UpdateUser::with_age((n % 100) as u32);
+            let updates = batch(n).map(|username| BatchUpdate { username, update: update.clone() }).collect();
+            async move { db.update_users(updates).await.map_err(Into::<ServerError>::into).unwrap() }
+        })
+    });
+    group.bench_function("create_and_delete_users", |b| {
+        b.to_async(&runtime).iter(|| {
+            n += 1;
+            let usernames: Vec<String> = (0..BATCH_SIZE).map(|i| format!("criterion{}_{}", n, i)).collect();
+            let users = usernames.iter().map(|username| CreateUser { username: username.clone() }).collect();
+            async move {
+                db.create_users(users).await.map_err(Into::<ServerError>::into).unwrap();
+                db.delete_users(usernames).await.map_err(Into::<ServerError>::into).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn in_process(c: &mut Criterion) {
+    let config = config();
+    bench_backend::<InMemoryDatabase>(c, "memory", &config);
+    bench_backend::<SqliteDatabase>(c, "sqlite", &config);
+    let mut inline = config.clone();
+    inline.executor.mode = ExecutorMode::Inline;
+    bench_backend::<SqliteDatabase>(c, "sqlite-inline", &inline);
+}
+
+fn networked(c: &mut Criterion) {
+    let config = config();
+    if url_set("POSTGRES_URL") {
+        bench_backend::<PostgresDatabase>(c, "postgres", &config);
+        bench_backend::<AsyncPostgresDatabase>(c, "postgres-async", &config);
+    }
+    if url_set("MYSQL_URL") {
+        bench_backend::<MySqlDatabase>(c, "mysql", &config);
+    }
+    if url_set("REDIS_URL") {
+        bench_backend::<RedisDatabase>(c, "redis", &config);
+    }
+    if url_set("MONGO_URL") {
+        bench_backend::<MongoDatabase>(c, "mongodb", &config);
+    }
+}
+
+criterion_group!(benches, in_process, networked);
+criterion_main!(benches);
@@ -0,0 +1,623 @@
+//! HTTP load generator behind the `bench` binary.
+//!
+//! A run either repeats one operation, reproducing one of the wrk scripts kept
+//! in the repository root (`post.lua`, `get.lua`, `update.lua`, `delete.lua`), or
+//! mixes operations following a workload profile. It is reported as JSON and CSV
+//! with HDR latency histograms instead of wrk's text output.
+
+mod report;
+mod workload;
+
+pub use report::{HistogramBucket, Interval, LatencySummary, OperationReport, Report};
+pub use workload::{Action, KeyDistribution, Keyspace, Profile, Workload, Zipfian};
+
+use anyhow::Context;
+use clap::Parser;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use reqwest::{Client, Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::database::User;
+use report::Stats;
+
+/// An operation on one user, matching a route of the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// `POST /users` for `user{n}`, like `post.lua`.
+    Create,
+    /// `GET /users/{username}`, like `get.lua`.
+    Get,
+    /// `PATCH /users/{username}` with an incrementing age, like `update.lua`.
+    Update,
+    /// `DELETE /users/user{n}`, like `delete.lua`, removing what `create` added.
+    Delete,
+    /// `GET /users/{username}` then `PATCH` it with the age read plus one, if it
+    /// is still at the version read, measured as a single operation. Lost races
+    /// answer `412 Precondition Failed`.
+    ReadModifyWrite,
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Get => "get",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::ReadModifyWrite => "read-modify-write",
+        }
+    }
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "create" | "post" => Ok(Operation::Create),
+            "get" => Ok(Operation::Get),
+            "update" | "patch" => Ok(Operation::Update),
+            "delete" => Ok(Operation::Delete),
+            "read-modify-write" => Ok(Operation::ReadModifyWrite),
+            _ => Err(format!(
+                "unknown operation `{}`, expected one of: create, get, update, delete, read-modify-write",
+                value
+            )),
+        }
+    }
+}
+
+/// Settings of a benchmark run.
+#[derive(Clone, Debug, Parser)]
+#[command(about = "Load generator for the benchmark server")]
+pub struct BenchArgs {
+    /// Base URL of the running server
+    #[arg(long, default_value = "http://localhost:3000")]
+    pub url: String,
+    /// Single operation to run: create, get, update, delete or read-modify-write
+    #[arg(long, default_value = "get")]
+    pub op: Operation,
+    /// Workload profile to run instead of `--op`: read-heavy, balanced,
+    /// write-heavy, read-modify-write or insert-then-read
+    #[arg(long, short = 'w', conflicts_with = "op")]
+    pub workload: Option<Profile>,
+    /// How workloads pick users: uniform, zipfian or latest [default: latest
+    /// for insert-then-read, zipfian otherwise]
+    #[arg(long, requires = "workload")]
+    pub distribution: Option<KeyDistribution>,
+    /// Users a workload runs over, numbered from `--start`
+    #[arg(long, default_value_t = 1000)]
+    pub records: u64,
+    /// Seed of the workload's random choices [default: random]
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Create the `--records` users before a workload runs
+    #[arg(long, requires = "workload")]
+    pub load: bool,
+    /// Requests in flight at once, each connection sending one at a time
+    #[arg(long, short = 'c', default_value_t = 100)]
+    pub concurrency: usize,
+    /// Length of the run in seconds
+    #[arg(long, short = 'd', default_value_t = 10.0)]
+    pub duration: f64,
+    /// Requests per second across all connections [default: as fast as possible].
+    /// Latencies are then measured from when each request was scheduled, so a
+    /// slow server cannot hide its queueing delay (coordinated omission)
+    #[arg(long, short = 'r')]
+    pub rate: Option<f64>,
+    /// User read by `get` and updated by `update` and `read-modify-write`
+    #[arg(long, default_value = "hello")]
+    pub username: String,
+    /// Number N of the first `userN` created or deleted by `create` and
+    /// `delete`, and of the first user of a workload
+    #[arg(long, default_value_t = 1)]
+    pub start: u64,
+    /// Write the JSON results to this file instead of stdout
+    #[arg(long, short = 'o')]
+    pub output: Option<PathBuf>,
+    /// Also write the per-operation percentiles to this CSV file
+    #[arg(long)]
+    pub csv: Option<PathBuf>,
+    /// Store the run in the results directory for later comparisons
+    #[arg(long, requires = "backend")]
+    pub save: bool,
+    /// Backend the server ran, recorded with saved runs
+    #[arg(long)]
+    pub backend: Option<String>,
+    /// Where `--save` stores runs, read by the `results` binary
+    #[arg(long, default_value = crate::results::DEFAULT_DIR)]
+    pub results_dir: PathBuf,
+}
+
+impl BenchArgs {
+    /// The profile name, or the operation name for single-operation runs.
+    pub fn workload_name(&self) -> &'static str {
+        match self.workload {
+            Some(profile) => profile.name(),
+            None => self.op.name(),
+        }
+    }
+
+    pub fn distribution(&self) -> Option<KeyDistribution> {
+        self.workload.map(|profile| self.distribution.unwrap_or(profile.default_distribution()))
+    }
+
+    /// The `n`th action of a single-operation run, counting from 0 across all
+    /// connections, which reproduces the counters of the Lua scripts.
+    fn single_action(&self, n: u64) -> Action {
+        let username = match self.op {
+            Operation::Create | Operation::Delete => format!("user{}", self.start + n),
+            _ => self.username.clone(),
+        };
+        Action {
+            operation: self.op,
+            username,
+            age: (n + 1) as u32,
+            insert: false,
+        }
+    }
+}
+
+/// One HTTP request of a run.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: Method,
+    /// `None` for `/users`, otherwise `/users/{username}`.
+    pub username: Option<String>,
+    pub body: Option<String>,
+    /// `If-Match` header, the `ETag` of the version the request expects.
+    pub if_match: Option<String>,
+}
+
+impl Request {
+    /// `ReadModifyWrite` is sent as a `Get` followed by an `Update`.
+    pub fn new(operation: Operation, username: &str, age: u32) -> Self {
+        match operation {
+            Operation::Create => Request {
+                method: Method::POST,
+                username: None,
+                body: Some(json!({ "username": username }).to_string()),
+                if_match: None,
+            },
+            Operation::Get | Operation::ReadModifyWrite => Request {
+                method: Method::GET,
+                username: Some(username.to_string()),
+                body: None,
+                if_match: None,
+            },
+            Operation::Update => Request {
+                method: Method::PATCH,
+                username: Some(username.to_string()),
+                body: Some(json!({ "age": age }).to_string()),
+                if_match: None,
+            },
+            Operation::Delete => Request {
+                method: Method::DELETE,
+                username: Some(username.to_string()),
+                body: None,
+                if_match: None,
+            },
+        }
+    }
+
+    fn url(&self, base: &Url) -> Url {
+        let mut url = base.clone();
+        // Checked to be a base URL when the run starts
+        let mut segments = url.path_segments_mut().expect("URL cannot be a base");
+        segments.pop_if_empty().push("users");
+        if let Some(username) = &self.username {
+            segments.push(username);
+        }
+        drop(segments);
+        url
+    }
+}
+
+/// HTTP client shared by the connections of a run.
+struct Sender {
+    client: Client,
+    base_url: Url,
+}
+
+impl Sender {
+    fn new(args: &BenchArgs) -> anyhow::Result<Self> {
+        let base_url = Url::parse(&args.url).with_context(|| format!("Invalid server URL {}", args.url))?;
+        anyhow::ensure!(!base_url.cannot_be_a_base(), "Invalid server URL {}", args.url);
+        let client = Client::builder()
+            .pool_max_idle_per_host(args.concurrency)
+            .build()
+            .context("Failed to create HTTP client")?;
+        Ok(Sender { client, base_url })
+    }
+
+    /// Returns the status, `ETag` and body of the response, or `None` when no
+    /// complete response was received.
+    async fn send(&self, request: Request) -> Option<(StatusCode, Option<String>, bytes::Bytes)> {
+        let mut builder = self.client.request(request.method.clone(), request.url(&self.base_url));
+        if let Some(body) = request.body {
+            builder = builder.header(reqwest::header::CONTENT_TYPE, "application/json").body(body);
+        }
+        if let Some(etag) = request.if_match {
+            builder = builder.header(reqwest::header::IF_MATCH, etag);
+        }
+        let response = builder.send().await.ok()?;
+        let status = response.status();
+        let etag = response.headers().get(reqwest::header::ETAG).and_then(|etag| etag.to_str().ok()).map(str::to_string);
+        // Reading the body to the end lets the connection be reused
+        let body = response.bytes().await.ok()?;
+        Some((status, etag, body))
+    }
+
+    /// Returns the status of the last request sent for `action`.
+    async fn perform(&self, action: &Action) -> Option<StatusCode> {
+        let (status, etag, body) = self.send(Request::new(action.operation, &action.username, action.age)).await?;
+        if action.operation != Operation::ReadModifyWrite || !status.is_success() {
+            return Some(status);
+        }
+        let user: User = serde_json::from_slice(&body).ok()?;
+        let mut update = Request::new(Operation::Update, &action.username, user.age.wrapping_add(1));
+        update.if_match = etag;
+        self.send(update).await.map(|(status, _, _)| status)
+    }
+}
+
+/// State shared by the connections of a run.
+struct Run {
+    args: BenchArgs,
+    sender: Sender,
+    workload: Option<Workload>,
+    /// Index of the next request, shared so `user{n}` names never repeat.
+    next: AtomicU64,
+    start: Instant,
+    deadline: Instant,
+}
+
+impl Run {
+    async fn connection(self: Arc<Self>, mut rng: StdRng) -> Stats {
+        let mut stats = Stats::default();
+        loop {
+            let n = self.next.fetch_add(1, Ordering::Relaxed);
+            let due = match self.args.rate {
+                // Requests are scheduled at fixed intervals from the start, whichever
+                // connection happens to send them
+                Some(rate) => {
+                    let at = self.start + Duration::from_secs_f64(n as f64 / rate);
+                    if at >= self.deadline {
+                        break;
+                    }
+                    tokio::time::sleep_until(at).await;
+                    Some(at)
+                }
+                None if Instant::now() >= self.deadline => break,
+                None => None,
+            };
+
+            let action = match &self.workload {
+                Some(workload) => workload.next(&mut rng),
+                None => self.args.single_action(n),
+            };
+            let sent = Instant::now();
+            let status = self.sender.perform(&action).await;
+            let service_time = sent.elapsed();
+            // When every connection is busy a scheduled request starts late, and
+            // that wait is part of the latency its user would have seen
+            let latency = due.map_or(service_time, |due| due.elapsed());
+            stats.record(action.operation, status, latency, service_time, self.start.elapsed());
+
+            if let Some(workload) = &self.workload
+                && action.insert
+                && status.is_some_and(|status| status.is_success())
+            {
+                workload.keyspace.inserted();
+            }
+        }
+        stats
+    }
+}
+
+fn validate(args: &BenchArgs) -> anyhow::Result<()> {
+    anyhow::ensure!(args.concurrency > 0, "concurrency must be at least 1");
+    anyhow::ensure!(args.duration > 0.0, "duration must be positive");
+    if let Some(rate) = args.rate {
+        anyhow::ensure!(rate > 0.0, "rate must be positive");
+    }
+    anyhow::ensure!(args.workload.is_none() || args.records > 0, "records must be at least 1");
+    Ok(())
+}
+
+/// Drives the server at `args.url` for `args.duration` seconds.
+pub async fn run(args: &BenchArgs) -> anyhow::Result<Report> {
+    validate(args)?;
+    let mut args = args.clone();
+    // Reported, so a workload can be replayed with the same choices
+    let seed = *args.seed.get_or_insert_with(rand::random);
+    let workload = args.workload.zip(args.distribution()).map(|(profile, distribution)| Workload {
+        profile,
+        keyspace: Keyspace::new(args.start, args.records, distribution),
+    });
+
+    let start = Instant::now();
+    let run = Arc::new(Run {
+        sender: Sender::new(&args)?,
+        workload,
+        next: AtomicU64::new(0),
+        start,
+        deadline: start + Duration::from_secs_f64(args.duration),
+        args: args.clone(),
+    });
+
+    let connections: Vec<_> = (0..args.concurrency)
+        .map(|i| tokio::spawn(run.clone().connection(StdRng::seed_from_u64(seed.wrapping_add(i as u64)))))
+        .collect();
+    let mut stats = Stats::default();
+    for connection in connections {
+        stats.merge(connection.await.context("Benchmark connection failed")?);
+    }
+    Ok(stats.into_report(&args, start.elapsed().as_secs_f64()))
+}
+
+/// Outcome of `load`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Loaded {
+    pub created: u64,
+    /// Users that were already there from an earlier load.
+    pub existing: u64,
+    pub failed: u64,
+}
+
+/// Creates the `args.records` users a workload runs over, skipping existing ones.
+pub async fn load(args: &BenchArgs) -> anyhow::Result<Loaded> {
+    validate(args)?;
+    let sender = Arc::new(Sender::new(args)?);
+    let next = Arc::new(AtomicU64::new(0));
+
+    let connections: Vec<_> = (0..args.concurrency)
+        .map(|_| {
+            let sender = sender.clone();
+            let next = next.clone();
+            let (start, records) = (args.start, args.records);
+            tokio::spawn(async move {
+                let mut loaded = Loaded::default();
+                loop {
+                    let key = next.fetch_add(1, Ordering::Relaxed);
+                    if key >= records {
+                        break loaded;
+                    }
+                    let request = Request::new(Operation::Create, &format!("user{}", start + key), 0);
+                    match sender.send(request).await {
+                        Some((status, _, _)) if status.is_success() => loaded.created += 1,
+                        Some((StatusCode::CONFLICT, _, _)) => loaded.existing += 1,
+                        _ => loaded.failed += 1,
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut total = Loaded::default();
+    for connection in connections {
+        let loaded = connection.await.context("Load connection failed")?;
+        total.created += loaded.created;
+        total.existing += loaded.existing;
+        total.failed += loaded.failed;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use axum::{Router, http::StatusCode, routing::{get, post}};
+
+    fn args(op: Operation, url: &str) -> BenchArgs {
+        BenchArgs::try_parse_from(["bench", "--url", url, "--op", op.name(), "-c", "4", "-d", "0.2"]).unwrap()
+    }
+
+    fn single_request(args: &BenchArgs, n: u64) -> Request {
+        let action = args.single_action(n);
+        Request::new(action.operation, &action.username, action.age)
+    }
+
+    /// Serves the user routes without a database: `hello` and `user{n}` exist,
+    /// nobody else does. Every user stays at version 3, and PATCHes expecting it
+    /// answer `204 No Content`.
+    async fn spawn_server() -> String {
+        let app = Router::new()
+            .route("/users", post(|| async { "created" }))
+            .route(
+                "/users/{username}",
+                get(|axum::extract::Path(username): axum::extract::Path<String>| async move {
+                    if username == "hello" || username.starts_with("user") {
+                        let user = json!({
+                            "id": 1, "username": username, "age": 41, "email": null, "display_name": null,
+                            "attributes": {}, "created_at": 1, "updated_at": 2, "version": 3,
+                        });
+                        Ok(([(axum::http::header::ETAG, "\"3\"")], axum::Json(user)))
+                    } else {
+                        Err(StatusCode::NOT_FOUND)
+                    }
+                })
+                .patch(|headers: axum::http::HeaderMap| async move {
+                    match headers.get(axum::http::header::IF_MATCH) {
+                        Some(etag) if etag == "\"3\"" => StatusCode::NO_CONTENT,
+                        Some(_) => StatusCode::PRECONDITION_FAILED,
+                        None => StatusCode::OK,
+                    }
+                })
+                .delete(|| async { StatusCode::OK }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn test_requests_match_lua_scripts() {
+        let base = Url::parse("http://localhost:3000/").unwrap();
+
+        let request = single_request(&args(Operation::Create, "http://localhost:3000"), 0);
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.url(&base).as_str(), "http://localhost:3000/users");
+        assert_eq!(request.body.as_deref(), Some(r#"{"username":"user1"}"#));
+
+        let request = single_request(&args(Operation::Update, "http://localhost:3000"), 4);
+        assert_eq!(request.method, Method::PATCH);
+        assert_eq!(request.url(&base).as_str(), "http://localhost:3000/users/hello");
+        assert_eq!(request.body.as_deref(), Some(r#"{"age":5}"#));
+
+        let request = single_request(&args(Operation::Delete, "http://localhost:3000"), 4);
+        assert_eq!(request.method, Method::DELETE);
+        assert_eq!(request.url(&base).as_str(), "http://localhost:3000/users/user5");
+
+        let mut get_args = args(Operation::Get, "http://localhost:3000");
+        get_args.username = "a b/c".to_string();
+        let request = single_request(&get_args, 0);
+        assert_eq!(request.method, Method::GET);
+        assert_eq!(request.url(&base).as_str(), "http://localhost:3000/users/a%20b%2Fc");
+    }
+
+    #[test]
+    fn test_operation_names() {
+        assert_eq!("post".parse(), Ok(Operation::Create));
+        assert_eq!("PATCH".parse(), Ok(Operation::Update));
+        assert!("put".parse::<Operation>().is_err());
+        assert_eq!(Operation::ReadModifyWrite.name().parse(), Ok(Operation::ReadModifyWrite));
+    }
+
+    #[test]
+    fn test_workload_flags() {
+        let args = BenchArgs::try_parse_from(["bench", "--workload", "insert-then-read"]).unwrap();
+        assert_eq!(args.workload_name(), "insert-then-read");
+        assert_eq!(args.distribution(), Some(KeyDistribution::Latest));
+        let args = BenchArgs::try_parse_from(["bench", "-w", "balanced", "--distribution", "uniform"]).unwrap();
+        assert_eq!(args.distribution(), Some(KeyDistribution::Uniform));
+        assert_eq!(BenchArgs::try_parse_from(["bench"]).unwrap().distribution(), None);
+
+        assert!(BenchArgs::try_parse_from(["bench", "--op", "get", "--workload", "balanced"]).is_err());
+        assert!(BenchArgs::try_parse_from(["bench", "--distribution", "zipfian"]).is_err());
+        assert!(BenchArgs::try_parse_from(["bench", "--load"]).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_run_counts_responses() {
+        let url = spawn_server().await;
+        let mut args = args(Operation::Get, &url);
+
+        let report = run(&args).await.unwrap();
+        assert!(report.total.requests > 0);
+        assert_eq!(report.total.successes, report.total.requests);
+        assert_eq!(report.total.status_codes, BTreeMap::from([(200, report.total.requests)]));
+        assert_eq!(report.total.transport_errors, 0);
+        assert!(report.total.latency.max_us >= report.total.latency.min_us);
+
+        args.username = "missing".to_string();
+        let report = run(&args).await.unwrap();
+        assert_eq!(report.total.successes, 0);
+        assert_eq!(report.total.status_codes, BTreeMap::from([(404, report.total.requests)]));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_run_at_fixed_rate() {
+        let url = spawn_server().await;
+        let mut args = args(Operation::Create, &url);
+        args.rate = Some(100.0);
+        args.duration = 0.5;
+
+        // Requests are scheduled every 10ms, so exactly 50 fit before the deadline
+        let report = run(&args).await.unwrap();
+        assert_eq!(report.total.requests, 50);
+        assert_eq!(report.total.successes, 50);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_fixed_rate_latency_includes_queueing() {
+        // Each response takes 30ms, but one connection is asked for one request
+        // every 10ms, so requests queue up behind each other
+        let app = Router::new().route(
+            "/users/{username}",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                StatusCode::OK
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut args = args(Operation::Get, &url);
+        args.concurrency = 1;
+        args.rate = Some(100.0);
+        args.duration = 0.3;
+
+        let report = run(&args).await.unwrap();
+        let get = &report.operations[&Operation::Get];
+        let service_time = get.service_time.as_ref().unwrap();
+        assert_eq!(get.requests, 30);
+        assert!(service_time.max_us < 200_000, "{:?}", service_time);
+        // The last request was due at 290ms but only sent after the 29 before it
+        assert!(get.latency.max_us > 500_000, "{:?}", get.latency);
+        assert!(get.latency.p50_us > service_time.p50_us * 2, "{:?}", get.latency);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_counts_transport_errors() {
+        // Bind then drop a listener to get a port nothing listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let report = run(&args(Operation::Delete, &url)).await.unwrap();
+        assert_eq!(report.total.requests, 0);
+        assert!(report.total.transport_errors > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_run_workload() {
+        let url = spawn_server().await;
+        let mut args = BenchArgs::try_parse_from(["bench", "--url", &url, "-w", "read-modify-write", "--records", "50"]).unwrap();
+        args.concurrency = 4;
+        args.duration = 0.2;
+        args.seed = Some(7);
+
+        let report = run(&args).await.unwrap();
+        assert_eq!(report.workload, "read-modify-write");
+        assert_eq!((report.distribution, report.records, report.seed), (Some(KeyDistribution::Zipfian), Some(50), Some(7)));
+        let gets = &report.operations[&Operation::Get];
+        let read_modify_writes = &report.operations[&Operation::ReadModifyWrite];
+        assert!(gets.requests > 0 && read_modify_writes.requests > 0);
+        assert_eq!(report.total.successes, report.total.requests);
+        // Each PATCH carried the `ETag` of its GET
+        assert_eq!(read_modify_writes.status_codes.keys().collect::<Vec<_>>(), [&204]);
+        assert_eq!(report.operations.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_load_creates_records() {
+        // Every other user already exists
+        let app = Router::new().route(
+            "/users",
+            post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+                let n: u64 = body["username"].as_str().unwrap()["user".len()..].parse().unwrap();
+                if n.is_multiple_of(2) { StatusCode::CONFLICT } else { StatusCode::CREATED }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let args = BenchArgs::try_parse_from(["bench", "--url", &url, "-w", "balanced", "--records", "20", "--load", "-c", "3"]).unwrap();
+        let loaded = load(&args).await.unwrap();
+        assert_eq!(loaded, Loaded { created: 10, existing: 10, failed: 0 });
+    }
+}
@@ -0,0 +1,434 @@
+use hdrhistogram::Histogram;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use super::{BenchArgs, KeyDistribution, Operation};
+
+/// Results of a run, serialized as the JSON output of `bench`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Report {
+    /// The operation of single-operation runs, otherwise the workload profile.
+    pub workload: String,
+    /// Workload runs only, like `records` and `seed`.
+    pub distribution: Option<KeyDistribution>,
+    pub records: Option<u64>,
+    pub seed: Option<u64>,
+    pub url: String,
+    pub concurrency: usize,
+    pub rate: Option<f64>,
+    /// Measured length of the run, including the requests in flight at the end.
+    pub duration_secs: f64,
+    /// All operations together, flattened into the top level of the JSON.
+    #[serde(flatten)]
+    pub total: OperationReport,
+    pub operations: BTreeMap<Operation, OperationReport>,
+    /// Every full second of the run, giving the samples that regression checks
+    /// compare between runs.
+    #[serde(default)]
+    pub timeline: Vec<Interval>,
+}
+
+/// Responses received during one second of a run.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Interval {
+    pub requests: u64,
+    pub mean_latency_us: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OperationReport {
+    /// Responses received, whatever their status.
+    pub requests: u64,
+    /// Responses with a 2xx status.
+    pub successes: u64,
+    /// Requests that got no response (connection refused, reset, ...).
+    pub transport_errors: u64,
+    #[serde(deserialize_with = "deserialize_status_codes")]
+    pub status_codes: BTreeMap<u16, u64>,
+    pub requests_per_sec: f64,
+    /// Time from when each request was due to its response. In fixed-rate runs a
+    /// request is due at its scheduled time, so time spent queued behind slow
+    /// responses counts too (coordinated omission correction).
+    pub latency: LatencySummary,
+    /// Time from when each request was actually sent to its response, only
+    /// reported for fixed-rate runs since it equals `latency` otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_time: Option<LatencySummary>,
+    /// Every recorded `latency` value with its count, omitted from the totals.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Vec<HistogramBucket>>,
+}
+
+/// Reads the status codes back from their JSON object keys. The derived impl
+/// would do it too, except under `#[serde(flatten)]` where keys stay strings.
+fn deserialize_status_codes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<u16, u64>, D::Error> {
+    BTreeMap::<String, u64>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(status, count)| status.parse().map(|status| (status, count)).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Latency percentiles in microseconds, precise to 3 significant digits.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub min_us: u64,
+    pub mean_us: f64,
+    pub stdev_us: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub p99_9_us: u64,
+    pub max_us: u64,
+}
+
+impl LatencySummary {
+    fn new(histogram: &Histogram<u64>) -> Self {
+        if histogram.is_empty() {
+            return LatencySummary::default();
+        }
+        LatencySummary {
+            min_us: histogram.min(),
+            mean_us: histogram.mean(),
+            stdev_us: histogram.stdev(),
+            p50_us: histogram.value_at_quantile(0.5),
+            p90_us: histogram.value_at_quantile(0.9),
+            p99_us: histogram.value_at_quantile(0.99),
+            p99_9_us: histogram.value_at_quantile(0.999),
+            max_us: histogram.max(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub value_us: u64,
+    pub count: u64,
+}
+
+impl Report {
+    /// One row per operation followed by the totals.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "operation,requests,successes,transport_errors,requests_per_sec,\
+             min_us,mean_us,stdev_us,p50_us,p90_us,p99_us,p99_9_us,max_us\n",
+        );
+        let rows = self.operations.iter().map(|(operation, report)| (operation.name(), report));
+        for (name, report) in rows.chain([("all", &self.total)]) {
+            let latency = &report.latency;
+            // Writing to a String cannot fail
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{:.2},{},{:.2},{:.2},{},{},{},{},{}",
+                name,
+                report.requests,
+                report.successes,
+                report.transport_errors,
+                report.requests_per_sec,
+                latency.min_us,
+                latency.mean_us,
+                latency.stdev_us,
+                latency.p50_us,
+                latency.p90_us,
+                latency.p99_us,
+                latency.p99_9_us,
+                latency.max_us,
+            );
+        }
+        csv
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests in {:.2}s, {} transport errors",
+            self.total.requests, self.duration_secs, self.total.transport_errors
+        )?;
+        for (operation, report) in &self.operations {
+            let ms = |us: u64| us as f64 / 1000.0;
+            let latency = &report.latency;
+            let statuses: Vec<_> = report.status_codes.iter().map(|(status, count)| format!("{}: {}", status, count)).collect();
+            writeln!(f, "{}: {} requests, status codes {}", operation.name(), report.requests, statuses.join(", "))?;
+            writeln!(
+                f,
+                "  Latency: p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, p99.9 {:.2}ms, max {:.2}ms",
+                ms(latency.p50_us),
+                ms(latency.p90_us),
+                ms(latency.p99_us),
+                ms(latency.p99_9_us),
+                ms(latency.max_us)
+            )?;
+        }
+        write!(f, "Requests/sec: {:.2}", self.total.requests_per_sec)
+    }
+}
+
+/// Counters of one operation on one connection.
+struct OperationStats {
+    requests: u64,
+    successes: u64,
+    transport_errors: u64,
+    status_codes: BTreeMap<u16, u64>,
+    latency: Histogram<u64>,
+    service_time: Histogram<u64>,
+}
+
+impl Default for OperationStats {
+    fn default() -> Self {
+        OperationStats {
+            requests: 0,
+            successes: 0,
+            transport_errors: 0,
+            status_codes: BTreeMap::new(),
+            // Auto-resizing, so no latency is ever out of range
+            latency: Histogram::new(3).expect("3 significant digits are supported"),
+            service_time: Histogram::new(3).expect("3 significant digits are supported"),
+        }
+    }
+}
+
+impl OperationStats {
+    fn merge(&mut self, other: &OperationStats) {
+        self.requests += other.requests;
+        self.successes += other.successes;
+        self.transport_errors += other.transport_errors;
+        for (status, count) in &other.status_codes {
+            *self.status_codes.entry(*status).or_default() += count;
+        }
+        // Adding only fails when the target cannot grow to fit, and these auto-resize
+        self.latency.add(&other.latency).expect("auto-resizing histograms can always be added");
+        self.service_time.add(&other.service_time).expect("auto-resizing histograms can always be added");
+    }
+
+    fn report(&self, elapsed_secs: f64, fixed_rate: bool, histogram: bool) -> OperationReport {
+        OperationReport {
+            requests: self.requests,
+            successes: self.successes,
+            transport_errors: self.transport_errors,
+            status_codes: self.status_codes.clone(),
+            requests_per_sec: self.requests as f64 / elapsed_secs,
+            latency: LatencySummary::new(&self.latency),
+            service_time: fixed_rate.then(|| LatencySummary::new(&self.service_time)),
+            histogram: histogram.then(|| {
+                self.latency
+                    .iter_recorded()
+                    .map(|value| HistogramBucket {
+                        value_us: value.value_iterated_to(),
+                        count: value.count_at_value(),
+                    })
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// `saturating_record` never resizes, so values are only clamped in the unlikely
+/// case that growing the histogram fails.
+fn record(histogram: &mut Histogram<u64>, duration: Duration) {
+    let us = duration.as_micros() as u64;
+    if histogram.record(us).is_err() {
+        histogram.saturating_record(us);
+    }
+}
+
+/// Counters of one second of the run.
+#[derive(Clone, Copy, Default)]
+struct Second {
+    requests: u64,
+    latency_us: u64,
+}
+
+/// Counters of one connection, merged into the report at the end of the run.
+#[derive(Default)]
+pub(super) struct Stats {
+    operations: BTreeMap<Operation, OperationStats>,
+    timeline: Vec<Second>,
+}
+
+impl Stats {
+    /// `status` is `None` when no response was received. `completed_at` is the
+    /// time since the start of the run.
+    pub(super) fn record(
+        &mut self,
+        operation: Operation,
+        status: Option<StatusCode>,
+        latency: Duration,
+        service_time: Duration,
+        completed_at: Duration,
+    ) {
+        let stats = self.operations.entry(operation).or_default();
+        let Some(status) = status else {
+            stats.transport_errors += 1;
+            return;
+        };
+        let second = completed_at.as_secs() as usize;
+        if self.timeline.len() <= second {
+            self.timeline.resize(second + 1, Second::default());
+        }
+        self.timeline[second].requests += 1;
+        self.timeline[second].latency_us += latency.as_micros() as u64;
+
+        stats.requests += 1;
+        if status.is_success() {
+            stats.successes += 1;
+        }
+        *stats.status_codes.entry(status.as_u16()).or_default() += 1;
+        record(&mut stats.latency, latency);
+        record(&mut stats.service_time, service_time);
+    }
+
+    pub(super) fn merge(&mut self, other: Stats) {
+        for (operation, stats) in other.operations {
+            self.operations.entry(operation).or_default().merge(&stats);
+        }
+        if self.timeline.len() < other.timeline.len() {
+            self.timeline.resize(other.timeline.len(), Second::default());
+        }
+        for (second, other) in self.timeline.iter_mut().zip(other.timeline) {
+            second.requests += other.requests;
+            second.latency_us += other.latency_us;
+        }
+    }
+
+    pub(super) fn into_report(self, args: &BenchArgs, elapsed_secs: f64) -> Report {
+        let fixed_rate = args.rate.is_some();
+        let mut total = OperationStats::default();
+        for stats in self.operations.values() {
+            total.merge(stats);
+        }
+        Report {
+            workload: args.workload_name().to_string(),
+            distribution: args.distribution(),
+            records: args.workload.map(|_| args.records),
+            seed: args.workload.and(args.seed),
+            url: args.url.clone(),
+            concurrency: args.concurrency,
+            rate: args.rate,
+            duration_secs: elapsed_secs,
+            total: total.report(elapsed_secs, fixed_rate, false),
+            operations: self
+                .operations
+                .iter()
+                .map(|(operation, stats)| (*operation, stats.report(elapsed_secs, fixed_rate, true)))
+                .collect(),
+            // The last second is cut short by the deadline, so only full ones are
+            // kept, and seconds without any response are kept as zeros
+            timeline: (0..args.duration.floor() as usize)
+                .map(|second| {
+                    let second = self.timeline.get(second).copied().unwrap_or_default();
+                    Interval {
+                        requests: second.requests,
+                        mean_latency_us: if second.requests == 0 {
+                            0.0
+                        } else {
+                            second.latency_us as f64 / second.requests as f64
+                        },
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn args(rate: Option<f64>) -> BenchArgs {
+        let mut args = BenchArgs::try_parse_from(["bench"]).unwrap();
+        args.rate = rate;
+        args
+    }
+
+    fn record_ms(stats: &mut Stats, operation: Operation, values: impl IntoIterator<Item = u64>) {
+        for ms in values {
+            let latency = Duration::from_millis(ms);
+            stats.record(operation, Some(StatusCode::OK), latency, latency, Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut stats = Stats::default();
+        record_ms(&mut stats, Operation::Get, 1..=1000);
+        let report = stats.into_report(&args(None), 1.0);
+
+        let latency = &report.operations[&Operation::Get].latency;
+        // 3 significant digits, so values are within 0.1% of the exact ones
+        let close = |value: u64, ms: u64| (value as f64 - ms as f64 * 1000.0).abs() <= ms as f64;
+        assert!(close(latency.min_us, 1), "{:?}", latency);
+        assert!(close(latency.p50_us, 500), "{:?}", latency);
+        assert!(close(latency.p90_us, 900), "{:?}", latency);
+        assert!(close(latency.p99_us, 990), "{:?}", latency);
+        assert!(close(latency.p99_9_us, 999), "{:?}", latency);
+        assert!(close(latency.max_us, 1000), "{:?}", latency);
+        assert_eq!(report.total.latency, *latency);
+    }
+
+    #[test]
+    fn test_merge_keeps_operations_apart() {
+        let mut first = Stats::default();
+        record_ms(&mut first, Operation::Get, [1, 2]);
+        first.record(Operation::Create, None, Duration::ZERO, Duration::ZERO, Duration::ZERO);
+        let mut second = Stats::default();
+        record_ms(&mut second, Operation::Get, [3]);
+        record_ms(&mut second, Operation::Create, [100]);
+        first.merge(second);
+
+        let report = first.into_report(&args(Some(10.0)), 2.0);
+        let get = &report.operations[&Operation::Get];
+        let create = &report.operations[&Operation::Create];
+        assert_eq!((get.requests, create.requests, create.transport_errors), (3, 1, 1));
+        assert_eq!(report.total.requests, 4);
+        assert_eq!(report.total.requests_per_sec, 2.0);
+        assert!(get.latency.max_us < 4_000);
+        assert!(create.latency.min_us >= 99_000);
+        assert!(create.service_time.is_some());
+        assert!(report.total.histogram.is_none());
+        let buckets = get.histogram.as_ref().unwrap();
+        assert_eq!(buckets.iter().map(|bucket| bucket.count).sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut stats = Stats::default();
+        record_ms(&mut stats, Operation::Update, [1]);
+        let csv = stats.into_report(&args(None), 1.0).to_csv();
+
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("operation,requests,successes,"));
+        assert!(lines[1].starts_with("update,1,1,0,1.00,"));
+        assert!(lines[2].starts_with("all,1,1,0,1.00,"));
+        assert_eq!(lines[1].split(',').count(), lines[0].split(',').count());
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut first = Stats::default();
+        let mut second = Stats::default();
+        first.record(Operation::Get, Some(StatusCode::OK), Duration::from_millis(2), Duration::ZERO, Duration::from_millis(100));
+        second.record(Operation::Get, Some(StatusCode::NOT_FOUND), Duration::from_millis(4), Duration::ZERO, Duration::from_millis(900));
+        second.record(Operation::Get, None, Duration::ZERO, Duration::ZERO, Duration::from_millis(1100));
+        second.record(Operation::Get, Some(StatusCode::OK), Duration::from_millis(1), Duration::ZERO, Duration::from_millis(2500));
+        first.merge(second);
+
+        let mut args = args(None);
+        args.duration = 3.5;
+        let report = first.into_report(&args, 3.6);
+        assert_eq!(
+            report.timeline,
+            [
+                Interval { requests: 2, mean_latency_us: 3000.0 },
+                Interval { requests: 0, mean_latency_us: 0.0 },
+                Interval { requests: 1, mean_latency_us: 1000.0 },
+            ]
+        );
+    }
+}
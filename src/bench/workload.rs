@@ -0,0 +1,338 @@
+//! Mixed workloads modelled on the YCSB core workloads.
+//!
+//! A workload runs over `records` users named `user{start}` to
+//! `user{start + records - 1}`, which have to exist before the run (see
+//! `--load`), and picks the user of every operation from a key distribution.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::Operation;
+
+/// Named operation mixes.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// 95% reads, 5% updates (YCSB B).
+    ReadHeavy,
+    /// 50% reads, 50% updates (YCSB A).
+    Balanced,
+    /// 5% reads, 95% updates.
+    WriteHeavy,
+    /// 50% reads, 50% read-modify-writes (YCSB F).
+    ReadModifyWrite,
+    /// 95% reads, 5% inserts of new users, reading recent users most (YCSB D).
+    InsertThenRead,
+}
+
+impl Profile {
+    /// Share of each operation, adding up to 1.
+    pub fn mix(self) -> &'static [(Operation, f64)] {
+        match self {
+            Profile::ReadHeavy => &[(Operation::Get, 0.95), (Operation::Update, 0.05)],
+            Profile::Balanced => &[(Operation::Get, 0.5), (Operation::Update, 0.5)],
+            Profile::WriteHeavy => &[(Operation::Get, 0.05), (Operation::Update, 0.95)],
+            Profile::ReadModifyWrite => &[(Operation::Get, 0.5), (Operation::ReadModifyWrite, 0.5)],
+            Profile::InsertThenRead => &[(Operation::Get, 0.95), (Operation::Create, 0.05)],
+        }
+    }
+
+    /// The distribution YCSB uses for the matching workload.
+    pub fn default_distribution(self) -> KeyDistribution {
+        match self {
+            Profile::InsertThenRead => KeyDistribution::Latest,
+            _ => KeyDistribution::Zipfian,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::ReadHeavy => "read-heavy",
+            Profile::Balanced => "balanced",
+            Profile::WriteHeavy => "write-heavy",
+            Profile::ReadModifyWrite => "read-modify-write",
+            Profile::InsertThenRead => "insert-then-read",
+        }
+    }
+
+    fn choose_operation(self, rng: &mut impl Rng) -> Operation {
+        let mut roll = rng.random::<f64>();
+        for &(operation, share) in self.mix() {
+            if roll < share {
+                return operation;
+            }
+            roll -= share;
+        }
+        // Rounding can leave a sliver past the last share
+        self.mix()[self.mix().len() - 1].0
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "read-heavy" => Ok(Profile::ReadHeavy),
+            "balanced" => Ok(Profile::Balanced),
+            "write-heavy" => Ok(Profile::WriteHeavy),
+            "read-modify-write" => Ok(Profile::ReadModifyWrite),
+            "insert-then-read" => Ok(Profile::InsertThenRead),
+            _ => Err(format!(
+                "unknown workload `{}`, expected one of: read-heavy, balanced, write-heavy, read-modify-write, insert-then-read",
+                value
+            )),
+        }
+    }
+}
+
+/// How the user of each operation is picked.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyDistribution {
+    /// Every user equally often.
+    Uniform,
+    /// A few hot users get most operations. Hot users are spread over the
+    /// keyspace rather than being the first ones, like YCSB's scrambled zipfian.
+    Zipfian,
+    /// The most recently inserted users get most operations.
+    Latest,
+}
+
+impl KeyDistribution {
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyDistribution::Uniform => "uniform",
+            KeyDistribution::Zipfian => "zipfian",
+            KeyDistribution::Latest => "latest",
+        }
+    }
+}
+
+impl FromStr for KeyDistribution {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "uniform" => Ok(KeyDistribution::Uniform),
+            "zipfian" => Ok(KeyDistribution::Zipfian),
+            "latest" => Ok(KeyDistribution::Latest),
+            _ => Err(format!("unknown key distribution `{}`, expected one of: uniform, zipfian, latest", value)),
+        }
+    }
+}
+
+/// Zipfian ranks in `0..items`, rank 0 being the most frequent, using the
+/// algorithm from Gray et al., "Quickly Generating Billion-Record Synthetic
+/// Databases", as YCSB does.
+#[derive(Debug)]
+pub struct Zipfian {
+    items: u64,
+    theta: f64,
+    zeta_n: f64,
+    alpha: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    /// YCSB's default skew.
+    pub const THETA: f64 = 0.99;
+
+    /// Takes time linear in `items` to sum the zeta constant.
+    pub fn new(items: u64, theta: f64) -> Self {
+        let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zeta_n = zeta(items);
+        Zipfian {
+            items,
+            theta,
+            zeta_n,
+            alpha: 1.0 / (1.0 - theta),
+            eta: (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta(2) / zeta_n),
+        }
+    }
+
+    pub fn next(&self, rng: &mut impl Rng) -> u64 {
+        let u = rng.random::<f64>();
+        let uz = u * self.zeta_n;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5_f64.powf(self.theta) {
+            return 1.min(self.items - 1);
+        }
+        let rank = (self.items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        rank.min(self.items - 1)
+    }
+}
+
+/// 64-bit FNV-1a, used to scatter zipfian ranks over the keyspace.
+fn fnv1a(value: u64) -> u64 {
+    value.to_le_bytes().iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The users a workload runs over, shared by all connections.
+#[derive(Debug)]
+pub struct Keyspace {
+    start: u64,
+    records: u64,
+    distribution: KeyDistribution,
+    zipfian: Option<Zipfian>,
+    /// Inserts handed out so far, so every insert gets a new user.
+    next_insert: AtomicU64,
+    /// Inserts that got a response, so reads only target users that exist.
+    inserted: AtomicU64,
+}
+
+impl Keyspace {
+    pub fn new(start: u64, records: u64, distribution: KeyDistribution) -> Self {
+        let zipfian = match distribution {
+            KeyDistribution::Uniform => None,
+            KeyDistribution::Zipfian | KeyDistribution::Latest => Some(Zipfian::new(records, Zipfian::THETA)),
+        };
+        Keyspace {
+            start,
+            records,
+            distribution,
+            zipfian,
+            next_insert: AtomicU64::new(0),
+            inserted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn username(&self, key: u64) -> String {
+        format!("user{}", self.start + key)
+    }
+
+    /// Picks one of the preloaded or already inserted users.
+    pub fn choose(&self, rng: &mut impl Rng) -> u64 {
+        let existing = self.records + self.inserted.load(Ordering::Relaxed);
+        match (&self.zipfian, self.distribution) {
+            (Some(zipfian), KeyDistribution::Latest) => existing - 1 - zipfian.next(rng),
+            (Some(zipfian), _) => fnv1a(zipfian.next(rng)) % existing,
+            (None, _) => rng.random_range(0..existing),
+        }
+    }
+
+    /// Claims the key of a new user, to be passed to `inserted` once created.
+    pub fn insert(&self) -> u64 {
+        self.records + self.next_insert.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn inserted(&self) {
+        self.inserted.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A profile running over a keyspace.
+#[derive(Debug)]
+pub struct Workload {
+    pub profile: Profile,
+    pub keyspace: Keyspace,
+}
+
+/// One operation of a workload.
+#[derive(Debug, PartialEq)]
+pub struct Action {
+    pub operation: Operation,
+    pub username: String,
+    /// Age written by `Update`.
+    pub age: u32,
+    /// Key claimed by `Create`, see `Keyspace::insert`.
+    pub insert: bool,
+}
+
+impl Workload {
+    pub fn next(&self, rng: &mut impl Rng) -> Action {
+        let operation = self.profile.choose_operation(rng);
+        let (key, insert) = match operation {
+            Operation::Create => (self.keyspace.insert(), true),
+            _ => (self.keyspace.choose(rng), false),
+        };
+        Action {
+            operation,
+            username: self.keyspace.username(key),
+            age: rng.random_range(0..1000),
+            insert,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_mixes_add_up() {
+        let profiles = [
+            Profile::ReadHeavy,
+            Profile::Balanced,
+            Profile::WriteHeavy,
+            Profile::ReadModifyWrite,
+            Profile::InsertThenRead,
+        ];
+        for profile in profiles {
+            let total: f64 = profile.mix().iter().map(|(_, share)| share).sum();
+            assert!((total - 1.0).abs() < 1e-9, "{:?}", profile);
+            assert_eq!(profile.name().parse(), Ok(profile));
+        }
+        assert!("workload-a".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn test_operation_shares() {
+        let workload = Workload {
+            profile: Profile::ReadHeavy,
+            keyspace: Keyspace::new(1, 100, KeyDistribution::Uniform),
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let reads = (0..10_000).filter(|_| workload.next(&mut rng).operation == Operation::Get).count();
+        assert!((9_300..9_700).contains(&reads), "{}", reads);
+    }
+
+    #[test]
+    fn test_zipfian_is_skewed() {
+        let zipfian = Zipfian::new(1000, Zipfian::THETA);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = vec![0u32; 1000];
+        for _ in 0..100_000 {
+            counts[zipfian.next(&mut rng) as usize] += 1;
+        }
+        assert!(counts[0] > counts[1] && counts[1] > counts[10] && counts[10] > counts[500]);
+        // With theta 0.99 over 1000 items, rank 0 takes about 13% of the draws
+        assert!((10_000..16_000).contains(&counts[0]), "{}", counts[0]);
+    }
+
+    #[test]
+    fn test_keys_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for distribution in [KeyDistribution::Uniform, KeyDistribution::Zipfian, KeyDistribution::Latest] {
+            let keyspace = Keyspace::new(1, 10, distribution);
+            assert!((0..1000).all(|_| keyspace.choose(&mut rng) < 10), "{:?}", distribution);
+            assert_eq!(keyspace.username(0), "user1");
+        }
+    }
+
+    #[test]
+    fn test_latest_follows_inserts() {
+        let keyspace = Keyspace::new(1, 100, KeyDistribution::Latest);
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(keyspace.insert(), 100);
+        assert_eq!(keyspace.insert(), 101);
+
+        // Claimed but not yet created users are not read
+        assert!((0..1000).all(|_| keyspace.choose(&mut rng) < 100));
+        keyspace.inserted();
+        keyspace.inserted();
+
+        let keys: Vec<_> = (0..1000).map(|_| keyspace.choose(&mut rng)).collect();
+        assert!(keys.iter().all(|key| *key < 102));
+        let newest = keys.iter().filter(|key| **key == 101).count();
+        assert!(newest > 100, "{}", newest);
+    }
+}
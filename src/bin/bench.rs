@@ -0,0 +1,44 @@
+use anyhow::Context;
+use clap::Parser;
+use diesel_sqlite_benchmark::bench::{self, BenchArgs};
+use diesel_sqlite_benchmark::results::ResultsStore;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = BenchArgs::parse();
+    // The human-readable summary goes to stderr, so stdout only holds the JSON
+    if args.load {
+        eprintln!("Loading {} users @ {}", args.records, args.url);
+        let loaded = bench::load(&args).await?;
+        eprintln!("{} created, {} already existing", loaded.created, loaded.existing);
+        anyhow::ensure!(loaded.failed == 0, "Failed to create {} users", loaded.failed);
+    }
+    eprintln!(
+        "Running {} for {}s @ {} with {} connections",
+        args.workload_name(),
+        args.duration,
+        args.url,
+        args.concurrency
+    );
+
+    let report = bench::run(&args).await?;
+    eprintln!("{}", report);
+
+    if let Some(path) = &args.csv {
+        std::fs::write(path, report.to_csv())
+            .with_context(|| format!("Failed to write results to {}", path.display()))?;
+    }
+
+    let json = serde_json::to_string_pretty(&report)?;
+    if args.save {
+        let backend = args.backend.as_deref().unwrap_or_default();
+        let id = ResultsStore::new(&args.results_dir).save(backend, report, None)?;
+        eprintln!("Saved run {}", id);
+    }
+    match &args.output {
+        Some(path) => std::fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write results to {}", path.display()))?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use clap::{Parser, Subcommand};
+use diesel_sqlite_benchmark::results::{self, ResultsStore, RunRecord, Thresholds, Verdict};
+use std::path::PathBuf;
+
+/// Runs are named by id, by the path of their JSON file, or `latest`.
+#[derive(Debug, Parser)]
+#[command(about = "Browse saved benchmark runs and check them for regressions")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+    /// Directory of the saved runs
+    #[arg(long, env = "RESULTS_DIR", default_value = results::DEFAULT_DIR, global = true)]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List saved runs, oldest first
+    List {
+        #[arg(long)]
+        backend: Option<String>,
+        #[arg(long, short = 'w')]
+        workload: Option<String>,
+    },
+    /// Make a run the baseline of its backend and workload
+    Baseline { run: String },
+    /// Compare a run with the baseline of its backend and workload, or with
+    /// another run given first. Exits with an error on regressions
+    Compare {
+        /// [BASELINE] CANDIDATE
+        #[arg(num_args = 1..=2, required = true)]
+        runs: Vec<String>,
+        /// Largest p-value counted as significant
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+        /// Smallest relative change that counts, 0.05 being 5%
+        #[arg(long, default_value_t = 0.05)]
+        threshold: f64,
+    },
+}
+
+fn describe(record: &RunRecord) -> String {
+    let commit = match &record.git {
+        Some(git) => format!("{:.10}{}", git.commit, if git.dirty { "-dirty" } else { "" }),
+        None => "unknown commit".to_string(),
+    };
+    format!(
+        "{} ({}, {:.0} req/s, p99 {:.2}ms)",
+        record.id,
+        commit,
+        record.report.total.requests_per_sec,
+        record.report.total.latency.p99_us as f64 / 1000.0
+    )
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let store = ResultsStore::new(&args.dir);
+    match args.command {
+        Command::List { backend, workload } => {
+            let runs = store.list()?.into_iter().filter(|run| {
+                backend.as_ref().is_none_or(|backend| &run.backend == backend)
+                    && workload.as_ref().is_none_or(|workload| &run.workload == workload)
+            });
+            for run in runs {
+                let baseline = store.baseline(&run.backend, &run.workload)?.is_some_and(|baseline| baseline.id == run.id);
+                println!("{}{}", describe(&run), if baseline { " [baseline]" } else { "" });
+            }
+        }
+        Command::Baseline { run } => {
+            let record = store.set_baseline(&run)?;
+            println!("Baseline of {} {} is now {}", record.backend, record.workload, describe(&record));
+        }
+        Command::Compare { runs, alpha, threshold } => {
+            let candidate = store.load(runs.last().expect("at least one run"))?;
+            let baseline = match &runs[..] {
+                [baseline, _] => store.load(baseline)?,
+                _ => store.baseline(&candidate.backend, &candidate.workload)?.ok_or_else(|| {
+                    anyhow::anyhow!("No baseline for {} {}, set one with `results baseline`", candidate.backend, candidate.workload)
+                })?,
+            };
+            println!("Baseline:  {}", describe(&baseline));
+            println!("Candidate: {}", describe(&candidate));
+            for difference in results::differences(&baseline, &candidate) {
+                println!("Warning: runs differ in {}", difference);
+            }
+            println!();
+
+            println!("{:<14} {:>12} {:>12} {:<5} {:>9} {:>8}", "Metric", "Baseline", "Candidate", "", "Change", "p-value");
+            let metrics = results::compare_reports(&baseline.report, &candidate.report, Thresholds { alpha, min_change: threshold });
+            for metric in &metrics {
+                println!("{}", metric);
+            }
+            let regressions = metrics.iter().filter(|metric| metric.verdict == Verdict::Regression).count();
+            anyhow::ensure!(regressions == 0, "{} metrics regressed", regressions);
+        }
+    }
+    Ok(())
+}
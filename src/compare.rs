@@ -0,0 +1,568 @@
+//! The `compare` subcommand, which runs the same workload against several
+//! backends one after the other and tabulates the results.
+//!
+//! Each backend is served from this process, on its own thread and tokio
+//! runtime so the load generator does not steal its workers, and is seeded with
+//! the same users first. Backends that fail to connect are reported as failed
+//! rather than aborting the comparison.
+//!
+//! The servers do not log unless `--log-modes` is given, in which case each
+//! backend runs once per mode with its logs written to a file, and the report
+//! lists the cost of each mode against `off`.
+
+use anyhow::Context;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{Layer, Registry, reload};
+
+use crate::bench::{self, BenchArgs, KeyDistribution, Operation, Profile, Report};
+use crate::config::{Config, DatabaseType, LogMode, LoggingConfig};
+use crate::database::Database;
+use crate::databases::*;
+use crate::logging;
+use crate::results::{self, ResultsStore};
+use crate::seed::{self, SeedArgs};
+use crate::server::{self, AppState};
+
+/// Flags of the `compare` subcommand.
+#[derive(Clone, Debug, clap::Args)]
+pub struct CompareArgs {
+    /// Backends to run, separated by commas [default: all of them]
+    #[arg(long, value_delimiter = ',')]
+    pub backends: Vec<DatabaseType>,
+    /// Workload profile: read-heavy, balanced, write-heavy, read-modify-write
+    /// or insert-then-read
+    #[arg(long, short = 'w', default_value = "read-heavy")]
+    pub workload: Profile,
+    /// How the workload picks users: uniform, zipfian or latest [default: per profile]
+    #[arg(long)]
+    pub distribution: Option<KeyDistribution>,
+    /// Users seeded into every backend before its run
+    #[arg(long, default_value_t = 1000)]
+    pub records: u64,
+    /// Seed of the workload's random choices, shared by all backends [default: random]
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Requests in flight at once
+    #[arg(long, short = 'c', default_value_t = 100)]
+    pub concurrency: usize,
+    /// Length of each backend's run in seconds
+    #[arg(long, short = 'd', default_value_t = 10.0)]
+    pub duration: f64,
+    /// Requests per second across all connections [default: as fast as possible]
+    #[arg(long, short = 'r')]
+    pub rate: Option<f64>,
+    /// Directory receiving comparison.json, comparison.md and comparison.html
+    #[arg(long, short = 'o', default_value = "comparison")]
+    pub output_dir: PathBuf,
+    /// Also store each backend's run in the results directory
+    #[arg(long)]
+    pub save: bool,
+    /// Where `--save` stores runs, read by the `results` binary
+    #[arg(long, default_value = results::DEFAULT_DIR)]
+    pub results_dir: PathBuf,
+    /// Run every backend once per log mode, e.g. off,errors-only,sampled,full,
+    /// writing the logs under <output-dir>/logs [default: no logging]
+    #[arg(long, value_delimiter = ',')]
+    pub log_modes: Vec<LogMode>,
+}
+
+/// Results of a comparison, serialized as `comparison.json`.
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub workload: Profile,
+    pub distribution: KeyDistribution,
+    pub records: u64,
+    pub seed: u64,
+    pub concurrency: usize,
+    /// Requested length of each run.
+    pub duration_secs: f64,
+    pub rate: Option<f64>,
+    pub backends: Vec<BackendResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackendResult {
+    pub backend: &'static str,
+    /// Log mode of the server, when comparing log modes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LogMode>,
+    /// Why the backend could not be benchmarked, e.g. its server is unreachable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<Report>,
+}
+
+/// A backend served over HTTP from a thread of its own.
+struct BackendServer {
+    address: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    thread: JoinHandle<anyhow::Result<()>>,
+}
+
+impl BackendServer {
+    /// Connects to the backend, seeds it and starts serving on a free local port.
+    async fn start<T: Database + 'static>(config: &Config, seed_args: SeedArgs) -> anyhow::Result<Self> {
+        let config = config.clone();
+        let (ready_tx, ready_rx) = oneshot::channel::<anyhow::Result<_>>();
+        let (shutdown, shutdown_rx) = oneshot::channel();
+
+        let thread = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().context("Failed to start server runtime")?;
+            runtime.block_on(async move {
+                let setup = async {
+                    let db = T::init(&config).await.context("Failed to initialize database")?;
+                    seed::seed(&db, &seed_args).await?;
+                    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.context("Failed to listen")?;
+                    let address = listener.local_addr()?;
+                    anyhow::Ok((db, listener, address))
+                };
+                let (db, listener, address) = match setup.await {
+                    Ok(setup) => setup,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return Ok(());
+                    }
+                };
+                let _ = ready_tx.send(Ok(address));
+                axum::serve(listener, server::router(AppState { db }, &config.logging))
+                    .with_graceful_shutdown(async {
+                        let _ = shutdown_rx.await;
+                    })
+                    .await
+                    .context("Server failed")
+            })
+        });
+
+        match ready_rx.await {
+            Ok(Ok(address)) => Ok(BackendServer { address, shutdown, thread }),
+            Ok(Err(e)) => Err(e),
+            // The thread ended without reporting, so it failed before setting up
+            Err(_) => Err(join(thread).await.err().unwrap_or_else(|| anyhow::anyhow!("Server thread exited"))),
+        }
+    }
+
+    async fn stop(self) -> anyhow::Result<()> {
+        let _ = self.shutdown.send(());
+        join(self.thread).await
+    }
+}
+
+async fn join(thread: JoinHandle<anyhow::Result<()>>) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || thread.join())
+        .await?
+        .map_err(|_| anyhow::anyhow!("Server thread panicked"))?
+}
+
+async fn start(backend: DatabaseType, config: &Config, seed_args: SeedArgs) -> anyhow::Result<BackendServer> {
+    match backend {
+        DatabaseType::Sqlite => BackendServer::start::<SqliteDatabase>(config, seed_args).await,
+        DatabaseType::InMemory => BackendServer::start::<InMemoryDatabase>(config, seed_args).await,
+        DatabaseType::Postgres => BackendServer::start::<PostgresDatabase>(config, seed_args).await,
+        DatabaseType::PostgresAsync => BackendServer::start::<AsyncPostgresDatabase>(config, seed_args).await,
+        DatabaseType::MySql => BackendServer::start::<MySqlDatabase>(config, seed_args).await,
+        DatabaseType::Redis => BackendServer::start::<RedisDatabase>(config, seed_args).await,
+        DatabaseType::MongoDB => BackendServer::start::<MongoDatabase>(config, seed_args).await,
+    }
+}
+
+async fn run_backend(backend: DatabaseType, config: &Config, bench_args: &BenchArgs) -> anyhow::Result<Report> {
+    let seed_args = SeedArgs {
+        users: bench_args.records,
+        start: bench_args.start,
+        batch_size: 10_000,
+    };
+    let server = start(backend, config, seed_args).await?;
+    let bench_args = BenchArgs {
+        url: format!("http://{}", server.address),
+        ..bench_args.clone()
+    };
+    let report = bench::run(&bench_args).await;
+    server.stop().await?;
+    report
+}
+
+/// Runs the workload against each of `args.backends`, reporting progress on stderr.
+pub async fn compare(config: &Config, args: &CompareArgs) -> anyhow::Result<Comparison> {
+    let backends = if args.backends.is_empty() { DatabaseType::ALL.to_vec() } else { args.backends.clone() };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let bench_args = BenchArgs {
+        url: String::new(),
+        op: Operation::Get,
+        workload: Some(args.workload),
+        distribution: args.distribution,
+        records: args.records,
+        seed: Some(seed),
+        load: false,
+        concurrency: args.concurrency,
+        duration: args.duration,
+        rate: args.rate,
+        username: String::new(),
+        start: 1,
+        output: None,
+        csv: None,
+        save: false,
+        backend: None,
+        results_dir: args.results_dir.clone(),
+    };
+    anyhow::ensure!(args.records > 0, "records must be at least 1");
+
+    let log_modes: Vec<_> = if args.log_modes.is_empty() { vec![None] } else { args.log_modes.iter().copied().map(Some).collect() };
+    let logs = if args.log_modes.is_empty() { None } else { Some(RunLogs::install(&args.output_dir.join("logs"))?) };
+
+    let mut results = Vec::new();
+    for backend in backends {
+        for &logging in &log_modes {
+            let mut config = config.clone();
+            config.logging.mode = logging.unwrap_or(LogMode::Off);
+            let result = BackendResult { backend: backend.name(), logging, error: None, report: None };
+            eprintln!("Running {} against {}", args.workload.name(), result.label());
+            if let Some(logs) = &logs {
+                logs.start(&config.logging, &format!("{}-{}", backend.name(), config.logging.mode.name()))?;
+            }
+            let run = run_backend(backend, &config, &bench_args).await;
+            if let Some(logs) = &logs {
+                logs.stop();
+            }
+            let result = match run {
+                Ok(report) => {
+                    eprintln!("{}", report);
+                    if args.save {
+                        let id = ResultsStore::new(&args.results_dir).save(backend.name(), report.clone(), Some(config))?;
+                        eprintln!("Saved run {}", id);
+                    }
+                    BackendResult { report: Some(report), ..result }
+                }
+                Err(e) => {
+                    eprintln!("Skipping {}: {:#}", result.label(), e);
+                    BackendResult { error: Some(format!("{:#}", e)), ..result }
+                }
+            };
+            results.push(result);
+        }
+    }
+
+    Ok(Comparison {
+        workload: args.workload,
+        distribution: bench_args.distribution().unwrap_or(args.workload.default_distribution()),
+        records: args.records,
+        seed,
+        concurrency: args.concurrency,
+        duration_secs: args.duration,
+        rate: args.rate,
+        backends: results,
+    })
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Sends the logs of the compared servers to a file per run, so that the log
+/// modes are measured paying for their writes.
+struct RunLogs {
+    dir: PathBuf,
+    layer: reload::Handle<Option<BoxedLayer>, Registry>,
+}
+
+impl RunLogs {
+    /// Installs the process's subscriber, which logs nothing until `start`.
+    fn install(dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let (layer, handle) = reload::Layer::new(None);
+        tracing_subscriber::registry()
+            .with(layer)
+            .try_init()
+            .context("Failed to install the log subscriber")?;
+        Ok(RunLogs { dir: dir.to_path_buf(), layer: handle })
+    }
+
+    fn start(&self, config: &LoggingConfig, name: &str) -> anyhow::Result<()> {
+        let path = self.dir.join(format!("{}.log", name));
+        let file = std::fs::File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        // A global level filter, as the per-layer ones cannot be swapped in
+        let layer = logging::fmt_layer(config, Arc::new(file), false).map(|layer| layer.and_then(LevelFilter::INFO).boxed());
+        self.layer.reload(layer).context("Failed to switch log files")
+    }
+
+    fn stop(&self) {
+        let _ = self.layer.reload(None);
+    }
+}
+
+impl Drop for RunLogs {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+const COLUMNS: [&str; 8] = ["Backend", "Requests/s", "Errors", "p50 (ms)", "p90 (ms)", "p99 (ms)", "p99.9 (ms)", "Max (ms)"];
+
+impl BackendResult {
+    /// The backend, followed by its log mode when comparing log modes.
+    fn label(&self) -> String {
+        match self.logging {
+            Some(mode) => format!("{} ({} logging)", self.backend, mode.name()),
+            None => self.backend.to_string(),
+        }
+    }
+
+    /// Cells of the comparison table, with `-` for the numbers of a failed backend.
+    fn cells(&self) -> [String; 8] {
+        let Some(report) = &self.report else {
+            let mut cells: [String; 8] = Default::default();
+            cells[0] = self.label();
+            cells[1..].fill("-".to_string());
+            return cells;
+        };
+        let total = &report.total;
+        // Error responses and requests without any response, over all requests sent
+        let sent = total.requests + total.transport_errors;
+        let failed = total.requests - total.successes + total.transport_errors;
+        let error_rate = if sent == 0 { 0.0 } else { failed as f64 / sent as f64 * 100.0 };
+        let ms = |us: u64| format!("{:.2}", us as f64 / 1000.0);
+        let latency = &total.latency;
+        [
+            self.label(),
+            format!("{:.0}", total.requests_per_sec),
+            format!("{:.2}%", error_rate),
+            ms(latency.p50_us),
+            ms(latency.p90_us),
+            ms(latency.p99_us),
+            ms(latency.p99_9_us),
+            ms(latency.max_us),
+        ]
+    }
+}
+
+impl Comparison {
+    fn title(&self) -> String {
+        let mut title = format!(
+            "{} workload, {} over {} users (seed {}), {} connections for {}s",
+            self.workload.name(),
+            self.distribution.name(),
+            self.records,
+            self.seed,
+            self.concurrency,
+            self.duration_secs
+        );
+        if let Some(rate) = self.rate {
+            let _ = write!(title, " at {} requests/s", rate);
+        }
+        title
+    }
+
+    fn errors(&self) -> impl Iterator<Item = (String, &str)> {
+        self.backends.iter().filter_map(|result| Some((result.label(), result.error.as_deref()?)))
+    }
+
+    /// The throughput and p99 latency of each log mode against the same backend
+    /// without logs, e.g. `sqlite, full: -12.3% requests/s, p99 +0.40 ms`.
+    fn logging_overhead(&self) -> Vec<String> {
+        let mut overhead = Vec::new();
+        for off in self.backends.iter().filter(|result| result.logging == Some(LogMode::Off)) {
+            let Some(off_report) = &off.report else { continue };
+            let logged = self.backends.iter().filter(|result| {
+                result.backend == off.backend && result.logging.is_some_and(|mode| mode != LogMode::Off)
+            });
+            for result in logged {
+                let (Some(mode), Some(report)) = (result.logging, &result.report) else { continue };
+                let throughput = report.total.requests_per_sec / off_report.total.requests_per_sec - 1.0;
+                let p99 = report.total.latency.p99_us as f64 - off_report.total.latency.p99_us as f64;
+                overhead.push(format!(
+                    "{}, {}: {:+.1}% requests/s, p99 {:+.2} ms",
+                    off.backend,
+                    mode.name(),
+                    throughput * 100.0,
+                    p99 / 1000.0
+                ));
+            }
+        }
+        overhead
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("## {}\n\n", self.title());
+        // Writing to a String cannot fail
+        let _ = writeln!(markdown, "| {} |", COLUMNS.join(" | "));
+        let _ = writeln!(markdown, "| --- |{}", " ---: |".repeat(COLUMNS.len() - 1));
+        for result in &self.backends {
+            let _ = writeln!(markdown, "| {} |", result.cells().join(" | "));
+        }
+        let overhead = self.logging_overhead();
+        if !overhead.is_empty() {
+            markdown.push_str("\nLogging overhead against `off`:\n\n");
+            for line in &overhead {
+                let _ = writeln!(markdown, "- {}", line);
+            }
+        }
+        let mut errors = self.errors().peekable();
+        if errors.peek().is_some() {
+            markdown.push_str("\nNot benchmarked:\n\n");
+            for (backend, error) in errors {
+                let _ = writeln!(markdown, "- {}: {}", backend, error.replace('\n', " "));
+            }
+        }
+        markdown
+    }
+
+    pub fn to_html(&self) -> String {
+        let title = escape_html(&self.title());
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+             body {{ font-family: sans-serif; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 4px 8px; }}\n\
+             td:not(:first-child) {{ text-align: right; }}\n\
+             </style>\n</head>\n<body>\n<h2>{}</h2>\n<table>\n<tr>",
+            title, title
+        );
+        for column in COLUMNS {
+            let _ = write!(html, "<th>{}</th>", column);
+        }
+        html.push_str("</tr>\n");
+        for result in &self.backends {
+            html.push_str("<tr>");
+            for cell in result.cells() {
+                let _ = write!(html, "<td>{}</td>", escape_html(&cell));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+        let overhead = self.logging_overhead();
+        if !overhead.is_empty() {
+            html.push_str("<p>Logging overhead against off:</p>\n<ul>\n");
+            for line in &overhead {
+                let _ = writeln!(html, "<li>{}</li>", escape_html(line));
+            }
+            html.push_str("</ul>\n");
+        }
+        let mut errors = self.errors().peekable();
+        if errors.peek().is_some() {
+            html.push_str("<p>Not benchmarked:</p>\n<ul>\n");
+            for (backend, error) in errors {
+                let _ = writeln!(html, "<li>{}: {}</li>", escape_html(&backend), escape_html(error));
+            }
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// Writes `comparison.json`, `comparison.md` and `comparison.html` into `dir`.
+    pub fn write(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let files = [
+            ("comparison.json", serde_json::to_string_pretty(self)? + "\n"),
+            ("comparison.md", self.to_markdown()),
+            ("comparison.html", self.to_html()),
+        ];
+        for (name, contents) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Command {
+        #[command(flatten)]
+        args: CompareArgs,
+    }
+
+    fn args(backends: &str) -> CompareArgs {
+        let args = ["compare", "--backends", backends, "-w", "balanced", "--records", "50", "-c", "4", "-d", "0.3"];
+        Command::try_parse_from(args).unwrap().args
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_compare_backends() {
+        let mut config = Config::for_tests();
+        config.postgres.url = "not a url".to_string();
+        let comparison = compare(&config, &args("memory,sqlite,postgres")).await.unwrap();
+
+        let backends: Vec<_> = comparison.backends.iter().map(|result| result.backend).collect();
+        assert_eq!(backends, ["memory", "sqlite", "postgres"]);
+        for result in &comparison.backends[..2] {
+            let report = result.report.as_ref().unwrap();
+            assert!(report.total.requests > 0, "{}", result.backend);
+            // Every workload user was seeded, so nothing is missing
+            assert_eq!(report.total.successes, report.total.requests, "{}", result.backend);
+            assert_eq!(report.seed, Some(comparison.seed));
+        }
+        let failed = &comparison.backends[2];
+        assert!(failed.report.is_none());
+        assert!(failed.error.as_ref().unwrap().contains("Invalid PostgreSQL URL"), "{:?}", failed.error);
+
+        let markdown = comparison.to_markdown();
+        assert!(markdown.contains("| memory | "), "{}", markdown);
+        assert!(markdown.contains("| postgres | - | - |"), "{}", markdown);
+        assert!(markdown.contains("- postgres: "), "{}", markdown);
+        assert_eq!(markdown.lines().filter(|line| line.starts_with('|')).count(), 5);
+        assert!(comparison.to_html().contains("<td>sqlite</td>"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_compare_log_modes() {
+        let mut args = args("memory");
+        args.log_modes = vec![LogMode::Off, LogMode::Full];
+        args.output_dir = std::env::temp_dir().join(format!("comparison_logs_{}", std::process::id()));
+        let comparison = compare(&Config::for_tests(), &args).await.unwrap();
+
+        let labels: Vec<_> = comparison.backends.iter().map(BackendResult::label).collect();
+        assert_eq!(labels, ["memory (off logging)", "memory (full logging)"]);
+        let logs = args.output_dir.join("logs");
+        assert_eq!(std::fs::read_to_string(logs.join("memory-off.log")).unwrap(), "");
+        let full = std::fs::read_to_string(logs.join("memory-full.log")).unwrap();
+        assert!(full.contains("finished processing request") && !full.contains('\x1b'), "{}", full);
+
+        let markdown = comparison.to_markdown();
+        assert!(markdown.contains("| memory (full logging) | "), "{}", markdown);
+        assert!(markdown.contains("Logging overhead against `off`:\n\n- memory, full: "), "{}", markdown);
+        std::fs::remove_dir_all(&args.output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_artifacts() {
+        let comparison = Comparison {
+            workload: Profile::ReadHeavy,
+            distribution: KeyDistribution::Zipfian,
+            records: 10,
+            seed: 1,
+            concurrency: 1,
+            duration_secs: 1.0,
+            rate: None,
+            backends: vec![BackendResult {
+                backend: "mysql",
+                logging: None,
+                error: Some("Failed <to> connect".to_string()),
+                report: None,
+            }],
+        };
+        let dir = std::env::temp_dir().join(format!("comparison_{}", std::process::id()));
+        comparison.write(&dir).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join("comparison.json")).unwrap()).unwrap();
+        assert_eq!(json["workload"], "read-heavy");
+        assert_eq!(json["backends"][0]["error"], "Failed <to> connect");
+        assert!(std::fs::read_to_string(dir.join("comparison.html")).unwrap().contains("Failed &lt;to&gt; connect"));
+        assert!(dir.join("comparison.md").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,11 +1,26 @@
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::config::Config;
+use crate::err::ServerError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct User {
     pub id: u64,
     pub username: String,
     pub age: u32,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    /// Free-form JSON object, patched key by key, see `UpdateUser`.
+    pub attributes: Map<String, Value>,
+    /// Milliseconds since the Unix epoch, from the server's clock, see `now_millis`.
+    pub created_at: u64,
+    /// Set on creation and by every update.
+    pub updated_at: u64,
+    /// Starts at 1 and goes up by one on every update, for conditional writes.
+    pub version: u64,
 }
 
 #[derive(Deserialize, Clone)]
@@ -13,18 +28,296 @@ pub struct CreateUser {
     pub username: String,
 }
 
-#[derive(Deserialize, Clone)]
+/// A JSON merge patch (RFC 7396) of a user: fields left out keep their value
+/// and `null` clears one. `attributes` is merged recursively, a `null` in it
+/// removing its key.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct UpdateUser {
+    /// Cannot be cleared, so `null` is rejected.
+    #[serde(default, deserialize_with = "present")]
+    pub age: Option<u32>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub display_name: Option<Option<String>>,
+    /// `null` clears all the attributes.
+    #[serde(default, deserialize_with = "nullable")]
+    pub attributes: Option<Option<Map<String, Value>>>,
+}
+
+impl UpdateUser {
+    /// An update setting only the age.
+    pub fn with_age(age: u32) -> Self {
+        UpdateUser { age: Some(age), ..Default::default() }
+    }
+
+    /// Applies the patch to `user`, leaving its id, version and timestamps alone.
+    pub fn apply(&self, user: &mut User) {
+        if let Some(age) = self.age {
+            user.age = age;
+        }
+        if let Some(email) = &self.email {
+            user.email = email.clone();
+        }
+        if let Some(display_name) = &self.display_name {
+            user.display_name = display_name.clone();
+        }
+        match &self.attributes {
+            Some(Some(patch)) => merge_patch(&mut user.attributes, patch),
+            Some(None) => user.attributes.clear(),
+            None => {}
+        }
+    }
+
+    /// For stores merging the attributes in place: whether to clear them before
+    /// merging, and the merge patch, `{}` leaving them as they are.
+    pub fn attributes_patch(&self) -> (bool, Map<String, Value>) {
+        match &self.attributes {
+            Some(Some(patch)) => (false, patch.clone()),
+            Some(None) => (true, Map::new()),
+            None => (false, Map::new()),
+        }
+    }
+}
+
+/// Merges `patch` into `target` following RFC 7396: a `null` removes its key,
+/// an object is merged into the object under its key, replacing anything
+/// else there, and any other value replaces the one under its key.
+pub fn merge_patch(target: &mut Map<String, Value>, patch: &Map<String, Value>) {
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            Value::Object(patch) => {
+                let entry = target.entry(key.clone()).or_insert_with(|| Value::Object(Map::new()));
+                if !entry.is_object() {
+                    *entry = Value::Object(Map::new());
+                }
+                if let Value::Object(target) = entry {
+                    merge_patch(target, patch);
+                }
+            }
+            value => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Deserializes a field that is there and not `null`, so that `null` is an error
+/// rather than read as a missing field.
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Deserializes a field that is there, telling `null` (`Some(None)`) apart from
+/// a missing field, which `#[serde(default)]` reads as `None`.
+fn nullable<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Milliseconds since the Unix epoch, the unit of `User::created_at` and
+/// `updated_at`.
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// The user as stored by `upsert_user`, and whether it was created rather than
+/// updated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upserted {
+    pub user: User,
+    pub created: bool,
+}
+
+/// One item of `update_users`: the user to update and its new values.
+#[derive(Deserialize, Clone)]
+pub struct BatchUpdate {
+    pub username: String,
+    #[serde(flatten)]
+    pub update: UpdateUser,
+}
+
+/// What the batch methods return: one result per item, in the order given.
+pub type BatchResult<T, E> = Result<Vec<Result<T, E>>, E>;
+
+/// A user inserted by `insert_users`, with its values set up front.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewUser {
+    pub username: String,
     pub age: u32,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub attributes: Map<String, Value>,
+}
+
+/// Sort key of `list_users`, with the key of the last user of the previous page,
+/// or `None` for the first page.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserCursor {
+    Id(Option<u64>),
+    /// Usernames compare in the store's collation, so the order may differ
+    /// between backends.
+    Username(Option<String>),
+}
+
+/// A keyset page of `list_users`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListUsers {
+    pub cursor: UserCursor,
+    pub limit: u32,
+}
+
+impl UserCursor {
+    /// The cursor of the page following `user`, in the same order.
+    pub fn after(&self, user: &User) -> UserCursor {
+        match self {
+            UserCursor::Id(_) => UserCursor::Id(Some(user.id)),
+            UserCursor::Username(_) => UserCursor::Username(Some(user.username.clone())),
+        }
+    }
+}
+
+/// Connection pool state, exported as gauges by `/metrics`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolStatus {
+    pub max_size: u64,
+    /// Connections handed out to requests.
+    pub in_use: u64,
+    /// Open connections waiting in the pool.
+    pub idle: u64,
+    /// Checkouts since startup, including those that failed, and the total
+    /// time they spent waiting for a connection.
+    pub checkouts: u64,
+    pub wait: Duration,
 }
 
 #[async_trait]
 pub trait Database: Send + Sync + Clone {
-    type Error: std::error::Error + Send + Sync + 'static;
+    /// Backend error, converted into a `ServerError` to pick the HTTP status code.
+    type Error: std::error::Error + Send + Sync + Into<ServerError> + 'static;
+    /// The `DatabaseType` name, labelling the backend's metrics.
+    const NAME: &'static str;
 
-    async fn init() -> Result<Self, Self::Error>;
+    /// Connects using the backend's section of `config` and creates the schema.
+    async fn init(config: &Config) -> Result<Self, Self::Error>;
+    /// Creates the user at age 0, without email, display name or attributes.
+    /// Fails with `ServerError::Conflict` if the username is already taken.
     async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error>;
+    /// Fails with `ServerError::NotFound` if no user has this username.
     async fn get_user(&self, username: String) -> Result<User, Self::Error>;
-    async fn update_user(&self, username: String, update: UpdateUser) -> Result<(), Self::Error>;
-    async fn delete_user(&self, username: String) -> Result<(), Self::Error>;
-}
\ No newline at end of file
+    /// Fails with `ServerError::NotFound` if no user has this username, and with
+    /// `ServerError::PreconditionFailed` if it is not at `expected_version`.
+    /// Returns the new version of the user.
+    ///
+    /// A user counts as matched even when the new values equal the stored ones,
+    /// so repeating the same update keeps succeeding. Its version goes up all
+    /// the same.
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error>;
+    /// Fails with `ServerError::NotFound` if no user has this username, and with
+    /// `ServerError::PreconditionFailed` if it is not at `expected_version`.
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error>;
+    /// Creates the user with the values of `update` over those of a new user, or
+    /// applies `update` to it if the username is taken, as one atomic
+    /// operation. Like a failed insert, an update may use up an id.
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error>;
+    /// Inserts all `users` through the store's bulk path rather than one
+    /// request per user.
+    ///
+    /// Fails with `ServerError::Conflict` if a username is already taken, in which
+    /// case some of the other users may have been inserted.
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error>;
+    async fn count_users(&self) -> Result<u64, Self::Error>;
+    /// Up to `list.limit` users following the cursor, in its order. Stores seek
+    /// to the cursor through their index on the key instead of skipping rows,
+    /// except the in-memory map, which has no order and scans.
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error>;
+    /// Removes every user. Ids keep increasing from where they were.
+    async fn delete_all_users(&self) -> Result<(), Self::Error>;
+
+    // Batch variants of the single-user methods, sent to the store in as few
+    // round trips as it allows. Usernames must be distinct within a batch.
+    //
+    // A taken username (`create_users`) or a missing user (the others) fails its
+    // item only. Any other error fails the whole call, in which case some of the
+    // items may have been applied by the stores without transactions.
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error>;
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error>;
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error>;
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error>;
+
+    /// `None` for backends without a connection pool.
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            value => panic!("not an object: {}", value),
+        }
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // The example of RFC 7396, section 3
+        let mut target = object(json!({
+            "title": "Goodbye!",
+            "author": { "givenName": "John", "familyName": "Doe" },
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged",
+        }));
+        merge_patch(&mut target, &object(json!({
+            "title": "Hello!",
+            "phoneNumber": "+01-123-456-7890",
+            "author": { "familyName": null },
+            "tags": ["example"],
+        })));
+        assert_eq!(Value::Object(target), json!({
+            "title": "Hello!",
+            "author": { "givenName": "John" },
+            "tags": ["example"],
+            "content": "This will be unchanged",
+            "phoneNumber": "+01-123-456-7890",
+        }));
+
+        // An object replaces a scalar, dropping its own nulls
+        let mut target = object(json!({ "a": 1 }));
+        merge_patch(&mut target, &object(json!({ "a": { "b": null, "c": 2 }, "d": null })));
+        assert_eq!(Value::Object(target), json!({ "a": { "c": 2 } }));
+    }
+
+    #[test]
+    fn test_update_user_tells_null_from_missing() {
+        let update: UpdateUser = serde_json::from_value(json!({ "email": null, "attributes": { "a": null } })).unwrap();
+        assert_eq!(update, UpdateUser {
+            email: Some(None),
+            attributes: Some(Some(object(json!({ "a": null })))),
+            ..Default::default()
+        });
+        assert!(serde_json::from_value::<UpdateUser>(json!({ "age": null })).is_err());
+
+        // Flattened into a batch item
+        let item: BatchUpdate = serde_json::from_value(json!({ "username": "a", "age": 3, "display_name": null })).unwrap();
+        assert_eq!(item.update, UpdateUser { age: Some(3), display_name: Some(None), ..Default::default() });
+
+        let mut user = User { email: Some("a@example.com".to_string()), ..Default::default() };
+        update.apply(&mut user);
+        assert_eq!((user.email, user.attributes), (None, Map::new()));
+        assert_eq!(UpdateUser { attributes: Some(None), ..Default::default() }.attributes_patch(), (true, Map::new()));
+    }
+}
@@ -0,0 +1,79 @@
+//! Per-item results of the batch `Database` methods for backends whose batch
+//! statements return the usernames they affected, rather than one outcome per item.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::database::User;
+use crate::err::ServerError;
+
+/// `Ok` for the usernames that were created, a conflict for the taken ones.
+pub fn created<'a>(
+    usernames: impl IntoIterator<Item = &'a String>,
+    created: &HashSet<String>,
+) -> Vec<Result<(), ServerError>> {
+    usernames
+        .into_iter()
+        .map(|username| {
+            if created.contains(username) {
+                Ok(())
+            } else {
+                Err(ServerError::Conflict(format!("User already exists: {}", username)))
+            }
+        })
+        .collect()
+}
+
+/// `Ok` for the usernames that matched a user, not found for the others.
+pub fn matched<'a>(
+    usernames: impl IntoIterator<Item = &'a String>,
+    matched: &HashSet<String>,
+) -> Vec<Result<(), ServerError>> {
+    usernames
+        .into_iter()
+        .map(|username| {
+            if matched.contains(username) {
+                Ok(())
+            } else {
+                Err(ServerError::NotFound(format!("User not found: {}", username)))
+            }
+        })
+        .collect()
+}
+
+/// The user of each username among `users`, in the order of `usernames`.
+pub fn found(usernames: &[String], users: Vec<User>) -> Vec<Result<User, ServerError>> {
+    let mut users: HashMap<String, User> = users.into_iter().map(|user| (user.username.clone(), user)).collect();
+    usernames
+        .iter()
+        .map(|username| {
+            users
+                .remove(username)
+                .ok_or_else(|| ServerError::NotFound(format!("User not found: {}", username)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_follow_the_given_order() {
+        let usernames = ["b".to_string(), "a".to_string(), "c".to_string()];
+        let affected = HashSet::from(["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            created(&usernames, &affected),
+            [Ok(()), Ok(()), Err(ServerError::Conflict("User already exists: c".to_string()))]
+        );
+        assert_eq!(
+            matched(&usernames, &affected)[2],
+            Err(ServerError::NotFound("User not found: c".to_string()))
+        );
+
+        let user = |id, username: &str| User { id, username: username.to_string(), age: 0, version: 1, ..Default::default() };
+        let results = found(&usernames, vec![user(1, "a"), user(2, "b")]);
+        assert_eq!(results[0], Ok(user(2, "b")));
+        assert_eq!(results[1], Ok(user(1, "a")));
+        assert!(matches!(results[2], Err(ServerError::NotFound(_))));
+    }
+}
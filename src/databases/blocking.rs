@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::config::{ExecutorConfig, ExecutorMode};
+use crate::err::ServerError;
+
+/// Runs the synchronous r2d2 work of `SqliteDatabase` and `PostgresDatabase`
+/// without stalling the tokio worker threads that drive axum.
+#[derive(Clone)]
+pub struct BlockingExecutor {
+    mode: ExecutorMode,
+    permits: Arc<Semaphore>,
+}
+
+impl BlockingExecutor {
+    pub fn new(mode: ExecutorMode, concurrency: usize) -> Self {
+        BlockingExecutor {
+            mode,
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+
+    /// Defaults the concurrency to the connection pool size.
+    pub fn from_config(config: &ExecutorConfig, pool_size: usize) -> Self {
+        BlockingExecutor::new(config.mode, config.concurrency.unwrap_or(pool_size))
+    }
+
+    pub async fn run<F, R>(&self, job: F) -> Result<R, ServerError>
+    where
+        F: FnOnce() -> Result<R, ServerError> + Send + 'static,
+        R: Send + 'static,
+    {
+        match self.mode {
+            ExecutorMode::Blocking => {
+                // Waiting for a permit is async, so excess requests queue here instead
+                // of piling up blocked threads inside `pool.get()`
+                let _permit = self
+                    .permits
+                    .acquire()
+                    .await
+                    .map_err(|e| ServerError::Internal(format!("Blocking executor closed: {}", e)))?;
+                // The blocking thread would start without the caller's span otherwise
+                let span = tracing::Span::current();
+                tokio::task::spawn_blocking(move || span.in_scope(job))
+                    .await
+                    .map_err(|e| ServerError::Internal(format!("Blocking database task failed: {}", e)))?
+            }
+            ExecutorMode::Inline => {
+                // The synchronous postgres client refuses to run directly on a worker
+                // thread, `block_in_place` is the closest it can get to the old behaviour
+                match tokio::runtime::Handle::current().runtime_flavor() {
+                    tokio::runtime::RuntimeFlavor::CurrentThread => job(),
+                    _ => tokio::task::block_in_place(job),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_run_returns_job_result() {
+        for mode in [ExecutorMode::Blocking, ExecutorMode::Inline] {
+            let executor = BlockingExecutor::new(mode, 1);
+            assert_eq!(executor.run(|| Ok(42)).await, Ok(42));
+            assert_eq!(
+                executor.run(|| Err::<(), _>(ServerError::NotFound("missing".to_string()))).await,
+                Err(ServerError::NotFound("missing".to_string()))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocking_job_panic_is_internal_error() {
+        let executor = BlockingExecutor::new(ExecutorMode::Blocking, 1);
+        let result = executor.run(|| -> Result<(), ServerError> { panic!("boom") }).await;
+        assert!(matches!(result, Err(ServerError::Internal(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrency_is_limited_by_permits() {
+        let executor = BlockingExecutor::new(ExecutorMode::Blocking, 2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let executor = executor.clone();
+                let running = running.clone();
+                let max_running = max_running.clone();
+                tokio::spawn(async move {
+                    executor
+                        .run(move || {
+                            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            max_running.fetch_max(now, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(20));
+                            running.fetch_sub(1, Ordering::SeqCst);
+                            Ok(())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,612 @@
+//! Backend-agnostic test suite for the `Database` trait.
+//!
+//! Every backend module instantiates it with `conformance_tests!`. Networked
+//! backends pass the env var holding their URL and are skipped when it is unset,
+//! so `cargo test` stays green without servers while e.g.
+//! `POSTGRES_URL=postgresql://... cargo test postgres` runs the full suite.
+
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, UpdateUser, User, UserCursor,
+};
+use crate::err::ServerError;
+
+/// Initializes the backend, or returns `None` when `url_var` is given but unset.
+pub async fn connect<T: Database>(url_var: Option<&str>) -> Option<T> {
+    if let Some(var) = url_var
+        && std::env::var(var).is_err()
+    {
+        eprintln!("skipping conformance test: {} is not set", var);
+        return None;
+    }
+    Some(T::init(&Config::for_tests()).await.map_err(Into::into).expect("Failed to initialize database"))
+}
+
+/// Returns a username that is unique per test run, since networked stores keep
+/// their data between runs.
+fn unique_username(name: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    format!("{}_{}_{}", name, nanos, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+async fn create<T: Database>(db: &T, username: &str) -> Result<String, ServerError> {
+    db.create_user(CreateUser { username: username.to_string() }).await.map_err(Into::into)
+}
+
+async fn update<T: Database>(db: &T, username: &str, age: u32) -> Result<(), ServerError> {
+    db.update_user(username.to_string(), UpdateUser::with_age(age), None).await.map(|_| ()).map_err(Into::into)
+}
+
+async fn delete<T: Database>(db: &T, username: &str) -> Result<(), ServerError> {
+    db.delete_user(username.to_string(), None).await.map_err(Into::into)
+}
+
+async fn get_version<T: Database>(db: &T, username: &str) -> Result<u64, ServerError> {
+    Ok(db.get_user(username.to_string()).await.map_err(Into::into)?.version)
+}
+
+async fn get_age<T: Database>(db: &T, username: &str) -> Result<u32, ServerError> {
+    let user = db.get_user(username.to_string()).await.map_err(Into::into)?;
+    assert_eq!(user.username, username);
+    Ok(user.age)
+}
+
+/// Runs create, get, update, get, delete, get on a single username.
+async fn lifecycle<T: Database>(db: &T, username: &str) {
+    create(db, username).await.unwrap();
+    assert_eq!(get_age(db, username).await, Ok(0));
+
+    update(db, username, 42).await.unwrap();
+    assert_eq!(get_age(db, username).await, Ok(42));
+
+    delete(db, username).await.unwrap();
+    assert!(matches!(get_age(db, username).await, Err(ServerError::NotFound(_))));
+}
+
+pub async fn create_and_get<T: Database>(db: &T) {
+    let username = unique_username("create_and_get");
+    assert_eq!(
+        create(db, &username).await,
+        Ok(format!("User created with username: {}", username))
+    );
+
+    let user = db.get_user(username.clone()).await.map_err(Into::into).unwrap();
+    assert_eq!(user.username, username);
+    assert_eq!(user.age, 0);
+}
+
+pub async fn create_duplicate<T: Database>(db: &T) {
+    let username = unique_username("create_duplicate");
+    create(db, &username).await.unwrap();
+    assert!(matches!(create(db, &username).await, Err(ServerError::Conflict(_))));
+}
+
+pub async fn get_missing<T: Database>(db: &T) {
+    let username = unique_username("get_missing");
+    assert!(matches!(get_age(db, &username).await, Err(ServerError::NotFound(_))));
+}
+
+pub async fn update_and_get<T: Database>(db: &T) {
+    let username = unique_username("update_and_get");
+    create(db, &username).await.unwrap();
+
+    for age in [42, 42, 0, u32::MAX] {
+        update(db, &username, age).await.unwrap();
+        assert_eq!(get_age(db, &username).await, Ok(age));
+    }
+}
+
+pub async fn update_missing<T: Database>(db: &T) {
+    let username = unique_username("update_missing");
+    assert!(matches!(update(db, &username, 1).await, Err(ServerError::NotFound(_))));
+}
+
+pub async fn delete_and_get<T: Database>(db: &T) {
+    let username = unique_username("delete_and_get");
+    create(db, &username).await.unwrap();
+
+    delete(db, &username).await.unwrap();
+    assert!(matches!(get_age(db, &username).await, Err(ServerError::NotFound(_))));
+    assert!(matches!(delete(db, &username).await, Err(ServerError::NotFound(_))));
+
+    // The username is free again once deleted
+    create(db, &username).await.unwrap();
+}
+
+pub async fn unicode_username<T: Database>(db: &T) {
+    lifecycle(db, &unique_username("ユーザー_ñandú_😀")).await;
+}
+
+pub async fn special_characters_username<T: Database>(db: &T) {
+    lifecycle(db, &unique_username("user@#$%^&*()_+-=[]{}|;:,.<>?'\"\\")).await;
+}
+
+/// 255 characters is the longest username every backend has to accept.
+pub async fn long_username<T: Database>(db: &T) {
+    let prefix = unique_username("long");
+    let username = format!("{}{}", prefix, "a".repeat(255 - prefix.len()));
+    assert_eq!(username.chars().count(), 255);
+    lifecycle(db, &username).await;
+}
+
+pub async fn concurrent_creates<T: Database + 'static>(db: &T) {
+    let prefix = unique_username("concurrent_creates");
+    let tasks: Vec<_> = (0..32)
+        .map(|i| {
+            let db = db.clone();
+            let username = format!("{}_{}", prefix, i);
+            tokio::spawn(async move { create(&db, &username).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    let mut ids = HashSet::new();
+    for i in 0..32 {
+        let user = db.get_user(format!("{}_{}", prefix, i)).await.map_err(Into::into).unwrap();
+        assert!(ids.insert(user.id), "duplicate id {}", user.id);
+    }
+}
+
+pub async fn concurrent_duplicate_creates<T: Database + 'static>(db: &T) {
+    let username = unique_username("concurrent_duplicate_creates");
+    let tasks: Vec<_> = (0..16)
+        .map(|_| {
+            let db = db.clone();
+            let username = username.clone();
+            tokio::spawn(async move { create(&db, &username).await })
+        })
+        .collect();
+
+    let mut created = 0;
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(_) => created += 1,
+            Err(ServerError::Conflict(_)) => {}
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+    assert_eq!(created, 1);
+}
+
+pub async fn concurrent_updates<T: Database + 'static>(db: &T) {
+    let username = unique_username("concurrent_updates");
+    create(db, &username).await.unwrap();
+    let id = db.get_user(username.clone()).await.map_err(Into::into).unwrap().id;
+
+    let tasks: Vec<_> = (1..=32)
+        .map(|age| {
+            let db = db.clone();
+            let username = username.clone();
+            tokio::spawn(async move { update(&db, &username, age).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    let user = db.get_user(username).await.map_err(Into::into).unwrap();
+    assert_eq!(user.id, id);
+    assert!((1..=32).contains(&user.age));
+}
+
+pub async fn upsert_creates_then_updates<T: Database>(db: &T) {
+    let username = unique_username("upsert");
+    let upsert = |age| db.upsert_user(username.clone(), UpdateUser::with_age(age));
+    let created = upsert(5).await.map_err(Into::into).unwrap();
+    assert!(created.created);
+    assert_eq!((created.user.username.as_str(), created.user.age), (username.as_str(), 5));
+    assert_eq!(db.get_user(username.clone()).await.map_err(Into::into).unwrap(), created.user);
+
+    // Keeping the same values still counts as an update
+    for (age, version) in [(6, 2), (6, 3)] {
+        let updated = upsert(age).await.map_err(Into::into).unwrap();
+        assert!(!updated.created);
+        assert!(updated.user.updated_at >= created.user.updated_at, "{:?}", updated.user);
+        assert_eq!(updated.user, User { age, version, updated_at: updated.user.updated_at, ..created.user.clone() });
+    }
+    assert_eq!(get_age(db, &username).await, Ok(6));
+    lifecycle(db, &unique_username("upsert_lifecycle")).await;
+}
+
+/// Upserting a user made by `create_user` updates it in place, keeping its id.
+pub async fn upsert_existing_user<T: Database>(db: &T) {
+    let username = unique_username("upsert_existing");
+    create(db, &username).await.unwrap();
+    let existing = db.get_user(username.clone()).await.map_err(Into::into).unwrap();
+
+    let upserted = db.upsert_user(username.clone(), UpdateUser::with_age(7)).await.map_err(Into::into).unwrap();
+    assert!(!upserted.created);
+    assert_eq!((upserted.user.id, upserted.user.age, upserted.user.version), (existing.id, 7, existing.version + 1));
+    assert_eq!(db.get_user(username).await.map_err(Into::into).unwrap(), upserted.user);
+}
+
+/// Exactly one of the upserts racing on a new username creates it.
+pub async fn concurrent_upserts<T: Database + 'static>(db: &T) {
+    let username = unique_username("concurrent_upserts");
+    let tasks: Vec<_> = (1..=16)
+        .map(|age| {
+            let db = db.clone();
+            let username = username.clone();
+            tokio::spawn(async move { db.upsert_user(username, UpdateUser::with_age(age)).await.map_err(Into::into) })
+        })
+        .collect();
+
+    let mut ids = HashSet::new();
+    let mut created = 0;
+    for task in tasks {
+        let upserted = task.await.unwrap().unwrap();
+        created += upserted.created as usize;
+        ids.insert(upserted.user.id);
+    }
+    assert_eq!(created, 1);
+    assert_eq!(ids.len(), 1, "{:?}", ids);
+    assert!((1..=16).contains(&get_age(db, &username).await.unwrap()));
+}
+
+/// Updates racing a delete must not bring the deleted user back, which a
+/// read-modify-write that is not atomic would do.
+pub async fn concurrent_update_and_delete<T: Database + 'static>(db: &T) {
+    let username = unique_username("concurrent_update_and_delete");
+    create(db, &username).await.unwrap();
+
+    let mut tasks: Vec<_> = (1..=32)
+        .map(|age| {
+            let db = db.clone();
+            let username = username.clone();
+            tokio::spawn(async move { update(&db, &username, age).await })
+        })
+        .collect();
+    let db_clone = db.clone();
+    let username_clone = username.clone();
+    tasks.push(tokio::spawn(async move { delete(&db_clone, &username_clone).await }));
+
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(()) | Err(ServerError::NotFound(_)) => {}
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+    assert!(matches!(get_age(db, &username).await, Err(ServerError::NotFound(_))));
+}
+
+pub async fn versions_and_conditional_writes<T: Database>(db: &T) {
+    let username = unique_username("versions");
+    let update_if = |age, version| db.update_user(username.clone(), UpdateUser::with_age(age), Some(version));
+    let delete_if = |version| db.delete_user(username.clone(), Some(version));
+    create(db, &username).await.unwrap();
+    assert_eq!(get_version(db, &username).await, Ok(1));
+
+    // Every update bumps the version, whether it expects one or not
+    assert_eq!(db.update_user(username.clone(), UpdateUser::with_age(0), None).await.map_err(Into::into), Ok(2));
+    assert_eq!(update_if(5, 2).await.map_err(Into::into), Ok(3));
+    let stale = update_if(6, 2).await.map_err(Into::into);
+    assert!(matches!(stale, Err(ServerError::PreconditionFailed(_))), "{:?}", stale);
+    let stale = delete_if(2).await.map_err(Into::into);
+    assert!(matches!(stale, Err(ServerError::PreconditionFailed(_))), "{:?}", stale);
+    let user = db.get_user(username.clone()).await.map_err(Into::into).unwrap();
+    assert_eq!((user.age, user.version), (5, 3));
+
+    let upserted = db.upsert_user(username.clone(), UpdateUser::with_age(7)).await.map_err(Into::into).unwrap();
+    assert_eq!(upserted.user.version, 4);
+    let update = BatchUpdate { username: username.clone(), update: UpdateUser::with_age(8) };
+    assert_eq!(items(db.update_users(vec![update]).await), [Ok(())]);
+    assert_eq!(get_version(db, &username).await, Ok(5));
+
+    delete_if(5).await.map_err(Into::into).unwrap();
+    assert!(matches!(update_if(9, 5).await.map_err(Into::into), Err(ServerError::NotFound(_))));
+    assert!(matches!(delete_if(5).await.map_err(Into::into), Err(ServerError::NotFound(_))));
+
+    // A user created again starts over
+    create(db, &username).await.unwrap();
+    assert_eq!(get_version(db, &username).await, Ok(1));
+}
+
+/// Of the updates racing from the same version, exactly one wins and the
+/// others find the user moved on, so no update is lost.
+pub async fn concurrent_conditional_updates<T: Database + 'static>(db: &T) {
+    let username = unique_username("concurrent_conditional_updates");
+    create(db, &username).await.unwrap();
+
+    let tasks: Vec<_> = (1..=16)
+        .map(|age| {
+            let db = db.clone();
+            let username = username.clone();
+            tokio::spawn(async move { db.update_user(username, UpdateUser::with_age(age), Some(1)).await.map_err(Into::into) })
+        })
+        .collect();
+    let mut updated = 0;
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(version) => {
+                assert_eq!(version, 2);
+                updated += 1;
+            }
+            Err(ServerError::PreconditionFailed(_)) => {}
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+    assert_eq!(updated, 1);
+    assert_eq!(get_version(db, &username).await, Ok(2));
+}
+
+/// The count is only compared as a lower bound, since other tests share
+/// networked stores and run at the same time.
+pub async fn insert_users_and_count<T: Database>(db: &T) {
+    let prefix = unique_username("insert_users");
+    let users: Vec<_> = (0..2500)
+        .map(|i| NewUser { username: format!("{}_{}", prefix, i), age: i, ..Default::default() })
+        .collect();
+    db.insert_users(users).await.map_err(Into::into).unwrap();
+
+    for i in [0, 1234, 2499] {
+        assert_eq!(get_age(db, &format!("{}_{}", prefix, i)).await, Ok(i));
+    }
+    let count = db.count_users().await.map_err(Into::into).unwrap();
+    assert!(count >= 2500, "{}", count);
+
+    // Inserted users get ids like created ones, and work with the other methods
+    let created = unique_username("insert_users_created");
+    create(db, &created).await.unwrap();
+    let first = db.get_user(format!("{}_0", prefix)).await.map_err(Into::into).unwrap();
+    let last = db.get_user(created).await.map_err(Into::into).unwrap();
+    assert!(first.id > 0 && last.id > first.id);
+    lifecycle(db, &unique_username("insert_users_lifecycle")).await;
+    db.insert_users(Vec::new()).await.map_err(Into::into).unwrap();
+}
+
+pub async fn insert_users_duplicate<T: Database>(db: &T) {
+    let username = unique_username("insert_users_duplicate");
+    create(db, &username).await.unwrap();
+
+    let users = vec![
+        NewUser { username: unique_username("insert_users_duplicate_new"), age: 1, ..Default::default() },
+        NewUser { username: username.clone(), age: 2, ..Default::default() },
+    ];
+    let result = db.insert_users(users).await.map_err(Into::into);
+    assert!(matches!(result, Err(ServerError::Conflict(_))), "{:?}", result);
+    // The existing user is left alone
+    assert_eq!(get_age(db, &username).await, Ok(0));
+}
+
+/// The per-item results of a batch call that must succeed as a whole.
+fn items<R, E: Into<ServerError>>(results: BatchResult<R, E>) -> Vec<Result<R, ServerError>> {
+    results.map_err(Into::into).unwrap().into_iter().map(|result| result.map_err(Into::into)).collect()
+}
+
+pub async fn batch_operations<T: Database>(db: &T) {
+    let [first, taken, second, missing] = ["batch_first", "batch_taken", "batch_second", "batch_missing"].map(unique_username);
+    create(db, &taken).await.unwrap();
+    let users = [&first, &taken, &second].map(|username| CreateUser { username: username.clone() });
+    let results = items(db.create_users(users.to_vec()).await);
+    assert_eq!(results[0], Ok(()));
+    assert_eq!(results[1], Err(ServerError::Conflict(format!("User already exists: {}", taken))));
+    assert_eq!(results[2], Ok(()));
+
+    let results = items(db.get_users(vec![second.clone(), missing.clone(), first.clone()]).await);
+    assert_eq!(results.len(), 3);
+    assert!(matches!(&results[0], Ok(user) if user.username == second && user.age == 0), "{:?}", results[0]);
+    assert_eq!(results[1], Err(ServerError::NotFound(format!("User not found: {}", missing))));
+    assert!(matches!(&results[2], Ok(user) if user.username == first && user.id > 0), "{:?}", results[2]);
+
+    let updates = [(&first, 5), (&missing, 6), (&second, 7)]
+        .map(|(username, age)| BatchUpdate { username: username.clone(), update: UpdateUser::with_age(age) });
+    let results = items(db.update_users(updates.to_vec()).await);
+    assert_eq!(results, [Ok(()), Err(ServerError::NotFound(format!("User not found: {}", missing))), Ok(())]);
+    assert_eq!(get_age(db, &first).await, Ok(5));
+    assert_eq!(get_age(db, &second).await, Ok(7));
+
+    let results = items(db.delete_users(vec![first.clone(), missing.clone()]).await);
+    assert_eq!(results, [Ok(()), Err(ServerError::NotFound(format!("User not found: {}", missing)))]);
+    assert!(matches!(get_age(db, &first).await, Err(ServerError::NotFound(_))));
+    assert_eq!(get_age(db, &taken).await, Ok(0));
+
+    assert_eq!(items(db.create_users(Vec::new()).await), []);
+    assert_eq!(items(db.get_users(Vec::new()).await), []);
+    assert_eq!(items(db.update_users(Vec::new()).await), []);
+    assert_eq!(items(db.delete_users(Vec::new()).await), []);
+}
+
+/// Deserializes a `PATCH` body like the server does.
+fn patch(body: Value) -> UpdateUser {
+    serde_json::from_value(body).unwrap()
+}
+
+/// Merge patches set, keep and clear the optional fields and merge the
+/// attributes recursively, in single, conditional, batch and upsert writes.
+pub async fn merge_patch_updates<T: Database>(db: &T) {
+    let username = unique_username("merge_patch");
+    let get = || async { db.get_user(username.clone()).await.map_err(Into::into).unwrap() };
+    let update = |body, version| db.update_user(username.clone(), patch(body), version);
+    create(db, &username).await.unwrap();
+    let created = get().await;
+    assert_eq!((created.email.clone(), created.display_name.clone()), (None, None));
+    assert!(created.attributes.is_empty(), "{:?}", created.attributes);
+    assert!(created.created_at > 0 && created.updated_at == created.created_at, "{:?}", created);
+
+    // Beyond 2^53, which a double would round
+    let body = json!({
+        "email": "merge@example.com",
+        "display_name": "Merge",
+        "attributes": { "plan": { "tier": "pro", "seats": 3 }, "tags": ["a", "b"], "big": 9_007_199_254_740_993_u64 },
+    });
+    assert_eq!(update(body, Some(1)).await.map_err(Into::into), Ok(2));
+    let user = get().await;
+    assert_eq!((user.age, user.email.as_deref(), user.display_name.as_deref()), (0, Some("merge@example.com"), Some("Merge")));
+    assert_eq!(Value::Object(user.attributes), json!({ "plan": { "tier": "pro", "seats": 3 }, "tags": ["a", "b"], "big": 9_007_199_254_740_993_u64 }));
+    assert_eq!(user.created_at, created.created_at);
+    assert!(user.updated_at >= user.created_at, "{:?}", (user.created_at, user.updated_at));
+
+    let body = json!({ "email": null, "attributes": { "plan": { "seats": null, "tier": "team" }, "tags": null, "locale": "de-DE" } });
+    update(body, None).await.map_err(Into::into).unwrap();
+    let user = get().await;
+    assert_eq!((user.email, user.display_name.as_deref()), (None, Some("Merge")));
+    assert_eq!(Value::Object(user.attributes), json!({ "plan": { "tier": "team" }, "big": 9_007_199_254_740_993_u64, "locale": "de-DE" }));
+
+    // A patch merged at a stale version is rejected rather than lost
+    let stale = update(json!({ "attributes": { "stale": true } }), Some(2)).await.map_err(Into::into);
+    assert!(matches!(stale, Err(ServerError::PreconditionFailed(_))), "{:?}", stale);
+    assert_eq!(update(json!({ "age": 5, "attributes": null }), Some(3)).await.map_err(Into::into), Ok(4));
+    let user = get().await;
+    assert_eq!((user.age, user.display_name.as_deref()), (5, Some("Merge")));
+    assert!(user.attributes.is_empty(), "{:?}", user.attributes);
+
+    let update = BatchUpdate { username: username.clone(), update: patch(json!({ "attributes": { "batch": { "n": 1 } } })) };
+    assert_eq!(items(db.update_users(vec![update]).await), [Ok(())]);
+    assert_eq!(Value::Object(get().await.attributes), json!({ "batch": { "n": 1 } }));
+
+    // Nulls in a patch creating the user are dropped too
+    let upserted_name = unique_username("merge_patch_upsert");
+    let upsert = |body| db.upsert_user(upserted_name.clone(), patch(body));
+    let upserted = upsert(json!({ "display_name": "Upserted", "attributes": { "a": { "b": 1, "c": null } } }))
+        .await.map_err(Into::into).unwrap();
+    assert!(upserted.created);
+    assert_eq!((upserted.user.age, upserted.user.version, upserted.user.display_name.as_deref()), (0, 1, Some("Upserted")));
+    assert_eq!(Value::Object(upserted.user.attributes), json!({ "a": { "b": 1 } }));
+    assert_eq!(upserted.user.updated_at, upserted.user.created_at);
+    let upserted = upsert(json!({ "attributes": { "a": { "d": 2 } } })).await.map_err(Into::into).unwrap();
+    assert!(!upserted.created);
+    assert_eq!(upserted.user.display_name.as_deref(), Some("Upserted"));
+    assert_eq!(Value::Object(upserted.user.attributes.clone()), json!({ "a": { "b": 1, "d": 2 } }));
+    assert_eq!(db.get_user(upserted_name).await.map_err(Into::into), Ok(upserted.user));
+}
+
+/// Bulk-inserted users keep every field given.
+pub async fn insert_users_extended_fields<T: Database>(db: &T) {
+    let attributes = json!({ "locale": "ja-JP", "plan": { "tier": "free" }, "tags": [] });
+    let user = NewUser {
+        username: unique_username("insert_users_extended"),
+        age: 33,
+        email: Some("extended@example.com".to_string()),
+        display_name: Some("Extended".to_string()),
+        attributes: attributes.as_object().cloned().unwrap(),
+    };
+    db.insert_users(vec![user.clone()]).await.map_err(Into::into).unwrap();
+    let stored = db.get_user(user.username.clone()).await.map_err(Into::into).unwrap();
+    assert_eq!((stored.age, stored.email, stored.display_name), (user.age, user.email, user.display_name));
+    assert_eq!(stored.attributes, user.attributes);
+    assert!(stored.created_at > 0 && stored.updated_at == stored.created_at, "{:?}", stored.created_at);
+    assert_eq!(stored.version, 1);
+}
+
+/// Pages through `cursor` until `done`, checking each page against the limit.
+async fn list_pages<T: Database>(db: &T, mut cursor: UserCursor, done: impl Fn(&User) -> bool) -> Vec<User> {
+    let mut listed = Vec::new();
+    loop {
+        let page = db.list_users(ListUsers { cursor: cursor.clone(), limit: 10 }).await.map_err(Into::into).unwrap();
+        assert!(page.len() <= 10, "{}", page.len());
+        let Some(last) = page.last() else {
+            return listed;
+        };
+        cursor = cursor.after(last);
+        let finished = page.iter().any(&done);
+        listed.extend(page);
+        if finished {
+            return listed;
+        }
+    }
+}
+
+/// Other tests add users to networked stores at the same time, so only the
+/// order of the users inserted here is checked among the listed ones.
+pub async fn list_users<T: Database>(db: &T) {
+    let prefix = unique_username("list_users");
+    let users: Vec<_> = (0..25)
+        .map(|i| NewUser { username: format!("{}_{}", prefix, (b'a' + i as u8) as char), age: i, ..Default::default() })
+        .collect();
+    db.insert_users(users.clone()).await.map_err(Into::into).unwrap();
+    let ours = |listed: &[User]| -> Vec<String> {
+        listed.iter().filter(|user| user.username.starts_with(&prefix)).map(|user| user.username.clone()).collect()
+    };
+    let expected: Vec<_> = users.iter().map(|user| user.username.clone()).collect();
+
+    let last = expected.last().unwrap().clone();
+    let listed = list_pages(db, UserCursor::Username(Some(prefix.clone())), |user| user.username == last).await;
+    assert_eq!(ours(&listed), expected);
+    assert!(listed.len() >= 25, "{}", listed.len());
+
+    let first = db.get_user(expected[0].clone()).await.map_err(Into::into).unwrap();
+    let last = db.get_user(last).await.map_err(Into::into).unwrap();
+    let listed = list_pages(db, UserCursor::Id(Some(first.id - 1)), |user| user.id >= last.id).await;
+    assert_eq!(ours(&listed), expected);
+    assert!(listed.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert_eq!(listed[0], first);
+
+    // The first page starts at the smallest key
+    let page = db.list_users(ListUsers { cursor: UserCursor::Id(None), limit: 1 }).await.map_err(Into::into).unwrap();
+    assert!(page.len() == 1 && page[0].id <= first.id, "{:?}", page);
+    let page = db.list_users(ListUsers { cursor: UserCursor::Username(None), limit: 0 }).await.map_err(Into::into).unwrap();
+    assert_eq!(page, Vec::new());
+}
+
+/// Pooled backends count the checkouts of the calls made so far.
+pub async fn pool_status<T: Database>(db: &T) {
+    create(db, &unique_username("pool_status")).await.unwrap();
+    if let Some(status) = db.pool_status() {
+        assert!(status.checkouts >= 1, "{:?}", status);
+        assert!(status.in_use + status.idle <= status.max_size, "{:?}", status);
+    }
+}
+
+/// Generates one `#[tokio::test]` per conformance check for a backend.
+///
+/// `conformance_tests!(SqliteDatabase)` always runs, while
+/// `conformance_tests!(PostgresDatabase, "POSTGRES_URL")` only runs when the
+/// env var is set.
+macro_rules! conformance_tests {
+    ($db:ty) => {
+        $crate::databases::conformance::conformance_tests!(@tests $db, None);
+    };
+    ($db:ty, $url_var:literal) => {
+        $crate::databases::conformance::conformance_tests!(@tests $db, Some($url_var));
+    };
+    (@tests $db:ty, $url_var:expr) => {
+        $crate::databases::conformance::conformance_tests!(@test $db, $url_var,
+            create_and_get,
+            create_duplicate,
+            get_missing,
+            update_and_get,
+            update_missing,
+            delete_and_get,
+            unicode_username,
+            special_characters_username,
+            long_username,
+            concurrent_creates,
+            concurrent_duplicate_creates,
+            concurrent_updates,
+            concurrent_update_and_delete,
+            upsert_creates_then_updates,
+            upsert_existing_user,
+            concurrent_upserts,
+            versions_and_conditional_writes,
+            concurrent_conditional_updates,
+            insert_users_and_count,
+            insert_users_duplicate,
+            list_users,
+            batch_operations,
+            merge_patch_updates,
+            insert_users_extended_fields,
+            pool_status,
+        );
+    };
+    (@test $db:ty, $url_var:expr, $($name:ident,)+) => {
+        $(
+            #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+            async fn $name() {
+                use $crate::databases::conformance;
+                if let Some(db) = conformance::connect::<$db>($url_var).await {
+                    conformance::$name(&db).await;
+                }
+            }
+        )+
+    };
+}
+
+pub(crate) use conformance_tests;
@@ -0,0 +1,207 @@
+use async_trait::async_trait;
+use dashmap::{DashMap, mapref::entry::Entry};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::Config;
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, UpdateUser, Upserted, User, UserCursor,
+    now_millis,
+};
+use crate::err::ServerError;
+
+/// Reference backend keeping users in a sharded concurrent map.
+///
+/// It has no I/O at all, so benchmarking it measures the axum/serde/tokio
+/// overhead that every other backend pays on top of its store.
+#[derive(Clone)]
+pub struct InMemoryDatabase {
+    users: Arc<DashMap<String, User>>,
+    last_id: Arc<AtomicU64>,
+}
+
+impl InMemoryDatabase {
+    /// A user just created under the next id, without the optional values.
+    fn new_user(&self, username: String) -> User {
+        // Ids start at 1 like the SQL auto-increment columns
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = now_millis();
+        User { id, username, created_at: now, updated_at: now, version: 1, ..Default::default() }
+    }
+
+    /// The `limit` users with the smallest keys above `after`, kept sorted in a
+    /// map of at most `limit` entries while scanning.
+    fn first_after<K: Ord>(&self, limit: u32, after: Option<&K>, key: impl Fn(&User) -> K) -> Vec<User> {
+        let mut page = BTreeMap::new();
+        for user in self.users.iter() {
+            let key = key(&user);
+            if after.is_some_and(|after| key <= *after) {
+                continue;
+            }
+            page.insert(key, user.clone());
+            if page.len() > limit as usize {
+                page.pop_last();
+            }
+        }
+        page.into_values().collect()
+    }
+}
+
+#[async_trait]
+impl Database for InMemoryDatabase {
+    type Error = ServerError;
+    const NAME: &'static str = "memory";
+
+    async fn init(_config: &Config) -> Result<Self, Self::Error> {
+        Ok(InMemoryDatabase {
+            users: Arc::new(DashMap::new()),
+            last_id: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error> {
+        match self.users.entry(user.username) {
+            Entry::Occupied(entry) => Err(ServerError::Conflict(format!("User already exists: {}", entry.key()))),
+            Entry::Vacant(entry) => {
+                let username = entry.key().clone();
+                entry.insert(self.new_user(username.clone()));
+                Ok(format!("User created with username: {}", username))
+            }
+        }
+    }
+
+    async fn get_user(&self, username: String) -> Result<User, Self::Error> {
+        match self.users.get(&username) {
+            Some(user) => Ok(user.clone()),
+            None => Err(ServerError::NotFound(format!("User not found: {}", username))),
+        }
+    }
+
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        match self.users.get_mut(&username) {
+            Some(user) if expected_version.is_some_and(|version| version != user.version) => {
+                Err(ServerError::PreconditionFailed(format!("User version mismatch: {}", username)))
+            }
+            Some(mut user) => {
+                update.apply(&mut user);
+                user.updated_at = now_millis();
+                user.version += 1;
+                Ok(user.version)
+            }
+            None => Err(ServerError::NotFound(format!("User not found: {}", username))),
+        }
+    }
+
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error> {
+        let matches = |_: &String, user: &User| expected_version.is_none_or(|version| version == user.version);
+        if self.users.remove_if(&username, matches).is_some() {
+            Ok(())
+        } else if self.users.contains_key(&username) {
+            Err(ServerError::PreconditionFailed(format!("User version mismatch: {}", username)))
+        } else {
+            Err(ServerError::NotFound(format!("User not found: {}", username)))
+        }
+    }
+
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error> {
+        match self.users.entry(username) {
+            Entry::Occupied(mut entry) => {
+                let user = entry.get_mut();
+                update.apply(user);
+                user.updated_at = now_millis();
+                user.version += 1;
+                Ok(Upserted { user: entry.get().clone(), created: false })
+            }
+            Entry::Vacant(entry) => {
+                let mut user = self.new_user(entry.key().clone());
+                update.apply(&mut user);
+                entry.insert(user.clone());
+                Ok(Upserted { user, created: true })
+            }
+        }
+    }
+
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error> {
+        for user in users {
+            match self.users.entry(user.username) {
+                Entry::Occupied(entry) => {
+                    return Err(ServerError::Conflict(format!("User already exists: {}", entry.key())));
+                }
+                Entry::Vacant(entry) => {
+                    let new_user = self.new_user(entry.key().clone());
+                    entry.insert(User {
+                        age: user.age,
+                        email: user.email,
+                        display_name: user.display_name,
+                        attributes: user.attributes,
+                        ..new_user
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn count_users(&self) -> Result<u64, Self::Error> {
+        Ok(self.users.len() as u64)
+    }
+
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error> {
+        Ok(match &list.cursor {
+            UserCursor::Id(after) => self.first_after(list.limit, after.as_ref(), |user| user.id),
+            UserCursor::Username(after) => self.first_after(list.limit, after.as_ref(), |user| user.username.clone()),
+        })
+    }
+
+    async fn delete_all_users(&self) -> Result<(), Self::Error> {
+        self.users.clear();
+        Ok(())
+    }
+
+    // Batches go through the single-user methods, a map having no round trips to save
+
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error> {
+        let mut results = Vec::with_capacity(users.len());
+        for user in users {
+            results.push(self.create_user(user).await.map(|_| ()));
+        }
+        Ok(results)
+    }
+
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error> {
+        let mut results = Vec::with_capacity(usernames.len());
+        for username in usernames {
+            results.push(self.get_user(username).await);
+        }
+        Ok(results)
+    }
+
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error> {
+        let mut results = Vec::with_capacity(updates.len());
+        for BatchUpdate { username, update } in updates {
+            results.push(self.update_user(username, update, None).await.map(|_| ()));
+        }
+        Ok(results)
+    }
+
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error> {
+        let mut results = Vec::with_capacity(usernames.len());
+        for username in usernames {
+            results.push(self.delete_user(username, None).await);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::databases::conformance::conformance_tests!(InMemoryDatabase);
+}
@@ -1,11 +1,21 @@
 pub mod sqlite;
+pub mod memory;
 pub mod postgres;
+pub mod postgres_async;
 pub mod mysql;
 pub mod redis;
 pub mod mongodb;
+pub mod batch;
+pub mod blocking;
+pub mod pool;
+
+#[cfg(test)]
+pub(crate) mod conformance;
 
 pub use sqlite::SqliteDatabase;
+pub use memory::InMemoryDatabase;
 pub use postgres::PostgresDatabase;
+pub use postgres_async::AsyncPostgresDatabase;
 pub use mysql::MySqlDatabase;
 pub use redis::RedisDatabase;
 pub use mongodb::MongoDatabase;
\ No newline at end of file
@@ -1,44 +1,262 @@
 use async_trait::async_trait;
 use mongodb::{
-    bson::{doc, oid::ObjectId},
-    options::{ClientOptions, IndexOptions},
+    bson::{self, Bson, doc, oid::ObjectId, Document},
+    error::{ErrorKind, InsertManyError},
+    event::{EventHandler, cmap::CmapEvent},
+    options::{ClientOptions, IndexOptions, ReturnDocument},
     Client, Collection, IndexModel,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-use crate::database::{CreateUser, Database, UpdateUser, User};
+use crate::config::Config;
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, PoolStatus, UpdateUser, Upserted, User,
+    UserCursor, now_millis,
+};
+use crate::databases::batch;
+use crate::databases::pool::PoolWaits;
 use crate::err::ServerError;
 
 #[derive(Serialize, Deserialize)]
 struct MongoUser {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    /// Numeric id exposed as `User.id`, allocated from the `counters` collection.
+    /// Stored as i64 because BSON has no unsigned integers. Documents written
+    /// before ids were allocated read back as 0.
+    #[serde(default)]
+    pub user_id: i64,
     pub username: String,
     pub age: u32,
+    /// This and the fields below read back empty or 0 from documents written
+    /// before they existed.
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl MongoUser {
+    /// A user to insert under `user_id`, with `user`'s values.
+    fn new(user_id: i64, user: NewUser) -> Self {
+        let now = now_millis() as i64;
+        MongoUser {
+            id: None,
+            user_id,
+            username: user.username,
+            age: user.age,
+            email: user.email,
+            display_name: user.display_name,
+            attributes: user.attributes,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+        }
+    }
+}
+
+impl From<MongoUser> for User {
+    fn from(user: MongoUser) -> Self {
+        User {
+            id: user.user_id as u64,
+            username: user.username,
+            age: user.age,
+            email: user.email,
+            display_name: user.display_name,
+            attributes: user.attributes,
+            created_at: user.created_at as u64,
+            updated_at: user.updated_at as u64,
+            version: user.version as u64,
+        }
+    }
+}
+
+/// The update pipeline applying `update`, which also fills in a user that an
+/// upsert creates from its filter's `{ username }`, under `user_id`. A pipeline
+/// rather than operators, so the attributes are merged by `merge_expression`.
+fn update_pipeline(update: &UpdateUser, user_id: Option<i64>) -> Result<Vec<Document>, ServerError> {
+    let now = now_millis() as i64;
+    let mut set = doc! {
+        "created_at": { "$ifNull": ["$created_at", now] },
+        "updated_at": now,
+        // 1 for a created user
+        "version": { "$add": [{ "$ifNull": ["$version", 0_i64] }, 1_i64] },
+    };
+    if let Some(user_id) = user_id {
+        set.insert("user_id", doc! { "$ifNull": ["$user_id", user_id] });
+    }
+    // i64 so ages above i32::MAX still deserialize back into u32
+    set.insert("age", match update.age {
+        Some(age) => Bson::Int64(age as i64),
+        None => doc! { "$ifNull": ["$age", 0_i64] }.into(),
+    });
+    // Strings go through `$literal`, as one starting with `$` would read as a field path
+    let literal = |value: &Option<String>| value.as_ref().map_or(Bson::Null, |value| doc! { "$literal": value }.into());
+    if let Some(email) = &update.email {
+        set.insert("email", literal(email));
+    }
+    if let Some(display_name) = &update.display_name {
+        set.insert("display_name", literal(display_name));
+    }
+    set.insert("attributes", match &update.attributes {
+        Some(Some(patch)) => merge_expression(Bson::String("$attributes".to_string()), patch, 0)?,
+        Some(None) => doc! { "$literal": {} }.into(),
+        None => doc! { "$ifNull": ["$attributes", { "$literal": {} }] }.into(),
+    });
+    Ok(vec![doc! { "$set": set }])
+}
+
+/// An expression merging `patch` into the value of `target` like `merge_patch`,
+/// with one `$setField` or `$unsetField` (MongoDB 5.0) per key of `patch`. The
+/// target of each level of nesting is bound to a variable named after `depth`.
+fn merge_expression(target: Bson, patch: &Map<String, Value>, depth: usize) -> Result<Bson, ServerError> {
+    let var = format!("target{}", depth);
+    let mut merged = Bson::String(format!("$${}", var));
+    for (key, value) in patch {
+        let field = doc! { "$literal": key };
+        merged = match value {
+            Value::Null => doc! { "$unsetField": { "field": field, "input": merged } },
+            Value::Object(patch) => {
+                let target = doc! { "$getField": { "field": field.clone(), "input": format!("$${}", var) } };
+                let value = merge_expression(target.into(), patch, depth + 1)?;
+                doc! { "$setField": { "field": field, "input": merged, "value": value } }
+            }
+            value => {
+                let value = bson::to_bson(value)
+                    .map_err(|e| ServerError::Validation(format!("Attribute `{}` cannot be stored: {}", key, e)))?;
+                doc! { "$setField": { "field": field, "input": merged, "value": { "$literal": value } } }
+            }
+        }
+        .into();
+    }
+    // Anything but an object is replaced, as missing fields are
+    let mut vars = Document::new();
+    vars.insert(var, doc! { "$cond": [{ "$eq": [{ "$type": target.clone() }, "object"] }, target, { "$literal": {} }] });
+    Ok(doc! { "$let": { "vars": vars, "in": merged } }.into())
+}
+
+/// Connections of the driver's pools, which it only reports through CMAP events.
+#[derive(Debug, Default)]
+struct PoolConnections {
+    open: AtomicU64,
+    in_use: AtomicU64,
+}
+
+impl PoolConnections {
+    fn handler(self: &Arc<Self>, waits: PoolWaits) -> EventHandler<CmapEvent> {
+        let connections = self.clone();
+        let decrement = |count: &AtomicU64| {
+            let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+        };
+        EventHandler::callback(move |event| match event {
+            CmapEvent::ConnectionCreated(_) => {
+                connections.open.fetch_add(1, Ordering::Relaxed);
+            }
+            CmapEvent::ConnectionClosed(_) => decrement(&connections.open),
+            CmapEvent::ConnectionCheckedOut(event) => {
+                connections.in_use.fetch_add(1, Ordering::Relaxed);
+                waits.record(event.duration);
+            }
+            CmapEvent::ConnectionCheckoutFailed(event) => waits.record(event.duration),
+            CmapEvent::ConnectionCheckedIn(_) => decrement(&connections.in_use),
+            _ => {}
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct MongoDatabase {
     collection: Arc<Collection<MongoUser>>,
+    counters: Arc<Collection<Document>>,
+    pool_size: u32,
+    connections: Arc<PoolConnections>,
+    waits: PoolWaits,
+}
+
+impl MongoDatabase {
+    /// Atomically increments the `users` counter and returns the new value, so
+    /// ids start at 1 and increase like the SQL auto-increment columns.
+    async fn next_user_id(&self) -> Result<i64, ServerError> {
+        self.allocate_user_ids(1).await
+    }
+
+    /// Matches the user named `username`, at `expected_version` if given.
+    /// Version 0 also matches documents written before users had versions.
+    fn version_filter(username: &str, expected_version: Option<u64>) -> Document {
+        let mut filter = doc! { "username": username };
+        match expected_version {
+            Some(0) => {
+                filter.insert("version", doc! { "$in": [0_i64, Bson::Null] });
+            }
+            Some(version) => {
+                filter.insert("version", version as i64);
+            }
+            None => {}
+        }
+        filter
+    }
+
+    /// Why a write on `username` matched no document: the user is missing, or
+    /// was found at another version than `expected_version`.
+    async fn unmatched(&self, username: &str, expected_version: Option<u64>) -> ServerError {
+        let not_found = ServerError::NotFound(format!("User not found: {}", username));
+        if expected_version.is_none() {
+            return not_found;
+        }
+        match self.collection.find_one(doc! { "username": username }).await {
+            Ok(Some(_)) => ServerError::PreconditionFailed(format!("User version mismatch: {}", username)),
+            Ok(None) => not_found,
+            Err(e) => ServerError::from(e).context("Check user version error"),
+        }
+    }
+
+    /// Reserves `count` consecutive ids and returns the last one.
+    async fn allocate_user_ids(&self, count: i64) -> Result<i64, ServerError> {
+        let counter = self.counters
+            .find_one_and_update(doc! { "_id": "users" }, doc! { "$inc": { "seq": count } })
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(|e| ServerError::from(e).context("Failed to generate user ID"))?
+            .ok_or_else(|| ServerError::Internal("Failed to generate user ID: counter missing".to_string()))?;
+
+        counter.get_i64("seq")
+            .map_err(|e| ServerError::Internal(format!("Failed to generate user ID: {}", e)))
+    }
 }
 
 #[async_trait]
 impl Database for MongoDatabase {
     type Error = ServerError;
+    const NAME: &'static str = "mongodb";
 
-    async fn init() -> Result<Self, Self::Error> {
-        // Use environment variable or default to localhost for connection
-        let mongo_url = std::env::var("MONGO_URL")
-            .unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
-        
-        let client_options = ClientOptions::parse(&mongo_url).await
-            .map_err(|e| ServerError::new(&format!("Failed to parse MongoDB URL: {}", e)))?;
+    async fn init(config: &Config) -> Result<Self, Self::Error> {
+        let mut client_options = ClientOptions::parse(&config.mongodb.url).await
+            .map_err(|e| ServerError::from(e).context("Failed to parse MongoDB URL"))?;
+        let connection_timeout = Duration::from_millis(config.mongodb.connection_timeout_ms);
+        client_options.max_pool_size = Some(config.mongodb.pool_size);
+        client_options.connect_timeout = Some(connection_timeout);
+        client_options.server_selection_timeout = Some(connection_timeout);
+        let connections = Arc::new(PoolConnections::default());
+        let waits = PoolWaits::default();
+        client_options.cmap_event_handler = Some(connections.handler(waits.clone()));
         
         let client = Client::with_options(client_options)
-            .map_err(|e| ServerError::new(&format!("Failed to create MongoDB client: {}", e)))?;
+            .map_err(|e| ServerError::from(e).context("Failed to create MongoDB client"))?;
         
-        let database = client.database("benchmark");
+        let database = client.database(&config.mongodb.database);
         let collection = database.collection::<MongoUser>("users");
         
         // Create index on username for faster lookups
@@ -48,31 +266,39 @@ impl Database for MongoDatabase {
             .build();
         
         collection.create_index(index).await
-            .map_err(|e| ServerError::new(&format!("Failed to create MongoDB index: {}", e)))?;
+            .map_err(|e| ServerError::from(e).context("Failed to create MongoDB index"))?;
+
+        // Sparse, so documents written before ids were allocated don't clash
+        let index = IndexModel::builder()
+            .keys(doc! { "user_id": 1 })
+            .options(IndexOptions::builder().unique(true).sparse(true).build())
+            .build();
+
+        collection.create_index(index).await
+            .map_err(|e| ServerError::from(e).context("Failed to create MongoDB index"))?;
 
         Ok(MongoDatabase {
             collection: Arc::new(collection),
+            counters: Arc::new(database.collection::<Document>("counters")),
+            pool_size: config.mongodb.pool_size,
+            connections,
+            waits,
         })
     }
 
     async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error> {
-        let mongo_user = MongoUser {
-            id: None,
-            username: user.username.clone(),
-            age: 0,
-        };
+        // Like a SQL sequence, an id taken by a failed insert is not reused
+        let user_id = self.next_user_id().await?;
+        let mongo_user = MongoUser::new(user_id, NewUser { username: user.username.clone(), ..Default::default() });
         
         let result = self.collection.insert_one(mongo_user).await;
         
         match result {
             Ok(_) => Ok(format!("User created with username: {}", user.username)),
-            Err(e) => {
-                if e.to_string().contains("duplicate key") {
-                    Err(format!("User already exists: {}", user.username).into())
-                } else {
-                    Err(format!("Create user `{}` error: {}", user.username, e).into())
-                }
-            }
+            Err(e) => match ServerError::from(e) {
+                ServerError::Conflict(_) => Err(ServerError::Conflict(format!("User already exists: {}", user.username))),
+                e => Err(e.context(&format!("Create user `{}` error", user.username))),
+            },
         }
     }
 
@@ -80,55 +306,198 @@ impl Database for MongoDatabase {
         let filter = doc! { "username": &username };
         
         let result = self.collection.find_one(filter).await
-            .map_err(|e| format!("Get user by username error: {}", e))?;
+            .map_err(|e| ServerError::from(e).context("Get user by username error"))?;
         
         match result {
-            Some(mongo_user) => {
-                // Use a simple hash of the ObjectId as the ID
-                let id = mongo_user.id
-                    .map(|oid| {
-                        use std::collections::hash_map::DefaultHasher;
-                        use std::hash::{Hash, Hasher};
-                        let mut hasher = DefaultHasher::new();
-                        oid.hash(&mut hasher);
-                        hasher.finish()
-                    })
-                    .unwrap_or(0);
-                
-                Ok(User {
-                    id,
-                    username: mongo_user.username,
-                    age: mongo_user.age,
-                })
-            },
-            None => Err(format!("User not found: {}", username).into()),
+            Some(mongo_user) => Ok(mongo_user.into()),
+            None => Err(ServerError::NotFound(format!("User not found: {}", username))),
         }
     }
 
-    async fn update_user(&self, username: String, update: UpdateUser) -> Result<(), Self::Error> {
-        let filter = doc! { "username": &username };
-        let update_doc = doc! { "$set": { "age": update.age as i32 } };
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        let filter = Self::version_filter(&username, expected_version);
+        let pipeline = update_pipeline(&update, None)?;
         
-        let result = self.collection.update_one(filter, update_doc).await
-            .map_err(|e| format!("Update user by username error: {}", e))?;
+        let result = self.collection.find_one_and_update(filter, pipeline)
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(|e| ServerError::from(e).context("Update user by username error"))?;
         
-        if result.matched_count == 0 {
-            return Err(format!("User not found: {}", username).into());
+        match result {
+            Some(mongo_user) => Ok(mongo_user.version as u64),
+            None => Err(self.unmatched(&username, expected_version).await),
         }
-        
-        Ok(())
     }
 
-    async fn delete_user(&self, username: String) -> Result<(), Self::Error> {
-        let filter = doc! { "username": &username };
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error> {
+        let filter = Self::version_filter(&username, expected_version);
         
         let result = self.collection.delete_one(filter).await
-            .map_err(|e| format!("Delete user by username error: {}", e))?;
+            .map_err(|e| ServerError::from(e).context("Delete user by username error"))?;
         
         if result.deleted_count == 0 {
-            return Err(format!("User not found: {}", username).into());
+            return Err(self.unmatched(&username, expected_version).await);
         }
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error> {
+        let filter = doc! { "username": &username };
+        // Updating first only allocates an id for a user that doesn't exist yet
+        let updated = self.collection.find_one_and_update(filter.clone(), update_pipeline(&update, None)?)
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(|e| ServerError::from(e).context(&format!("Upsert user `{}` error", username)))?;
+        if let Some(mongo_user) = updated {
+            return Ok(Upserted { created: false, user: mongo_user.into() });
+        }
+
+        // The id is only stored if the upsert inserts, which tells both cases
+        // apart should another request have created the user in the meantime
+        let user_id = self.next_user_id().await?;
+        let mongo_user = self.collection.find_one_and_update(filter, update_pipeline(&update, Some(user_id))?)
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(|e| ServerError::from(e).context(&format!("Upsert user `{}` error", username)))?
+            .ok_or_else(|| ServerError::Internal(format!("Upsert user `{}` error: no document returned", username)))?;
+
+        Ok(Upserted { created: mongo_user.user_id == user_id, user: mongo_user.into() })
+    }
+
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error> {
+        if users.is_empty() {
+            return Ok(());
+        }
+        let last_id = self.allocate_user_ids(users.len() as i64).await?;
+        let first_id = last_id - users.len() as i64 + 1;
+        let documents = users.into_iter().zip(first_id..).map(|(user, user_id)| MongoUser::new(user_id, user));
+
+        // Unordered, so the server may insert the documents in parallel
+        self.collection.insert_many(documents).ordered(false).await
+            .map_err(|e| ServerError::from(e).context("Insert users error"))?;
+        Ok(())
+    }
+
+    async fn count_users(&self) -> Result<u64, Self::Error> {
+        self.collection.count_documents(doc! {}).await
+            .map_err(|e| ServerError::from(e).context("Count users error"))
+    }
+
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error> {
+        // Both keys have a unique index, which the sorted range query walks
+        let (filter, sort) = match &list.cursor {
+            UserCursor::Id(after) => {
+                let after = i64::try_from(after.unwrap_or(0)).unwrap_or(i64::MAX);
+                (doc! { "user_id": { "$gt": after } }, doc! { "user_id": 1 })
+            }
+            UserCursor::Username(Some(after)) => (doc! { "username": { "$gt": after } }, doc! { "username": 1 }),
+            UserCursor::Username(None) => (doc! {}, doc! { "username": 1 }),
+        };
+        let mut cursor = self.collection.find(filter).sort(sort).limit(i64::from(list.limit)).await
+            .map_err(|e| ServerError::from(e).context("List users error"))?;
+
+        let mut users = Vec::new();
+        while cursor.advance().await.map_err(|e| ServerError::from(e).context("List users error"))? {
+            let mongo_user = cursor.deserialize_current()
+                .map_err(|e| ServerError::from(e).context("List users error"))?;
+            users.push(mongo_user.into());
+        }
+        Ok(users)
+    }
+
+    async fn delete_all_users(&self) -> Result<(), Self::Error> {
+        // Deletes the documents rather than dropping the collection, to keep its indexes
+        self.collection.delete_many(doc! {}).await
+            .map_err(|e| ServerError::from(e).context("Delete all users error"))?;
+        Ok(())
+    }
+
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error> {
+        if users.is_empty() {
+            return Ok(Vec::new());
+        }
+        let last_id = self.allocate_user_ids(users.len() as i64).await?;
+        let first_id = last_id - users.len() as i64 + 1;
+        let documents = users.iter().zip(first_id..).map(|(user, user_id)| {
+            MongoUser::new(user_id, NewUser { username: user.username.clone(), ..Default::default() })
+        });
+
+        // Unordered, so the documents after a duplicate are still inserted
+        let error = match self.collection.insert_many(documents).ordered(false).await {
+            Ok(_) => return Ok(users.iter().map(|_| Ok(())).collect()),
+            Err(e) => e,
+        };
+        let mut results: Vec<Result<(), ServerError>> = users.iter().map(|_| Ok(())).collect();
+        if let ErrorKind::InsertMany(InsertManyError { write_errors: Some(write_errors), write_concern_error: None, .. }) =
+            error.kind.as_ref()
+            && write_errors.iter().all(|write_error| write_error.code == 11000)
+        {
+            for write_error in write_errors {
+                let username = &users[write_error.index].username;
+                results[write_error.index] = Err(ServerError::Conflict(format!("User already exists: {}", username)));
+            }
+            return Ok(results);
+        }
+        Err(ServerError::from(error).context("Create users error"))
+    }
+
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error> {
+        let mut cursor = self.collection.find(doc! { "username": { "$in": &usernames } }).await
+            .map_err(|e| ServerError::from(e).context("Get users error"))?;
+
+        let mut users = Vec::with_capacity(usernames.len());
+        while cursor.advance().await.map_err(|e| ServerError::from(e).context("Get users error"))? {
+            let mongo_user = cursor.deserialize_current()
+                .map_err(|e| ServerError::from(e).context("Get users error"))?;
+            users.push(mongo_user.into());
+        }
+        Ok(batch::found(&usernames, users))
+    }
+
+    // A multi-document update or delete only reports how many documents it
+    // matched in total, and the per-document `bulkWrite` needs MongoDB 8.0, so
+    // these send one command per user
+
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error> {
+        let mut results = Vec::with_capacity(updates.len());
+        for BatchUpdate { username, update } in updates {
+            match self.update_user(username, update, None).await.map(|_| ()) {
+                result @ (Ok(()) | Err(ServerError::NotFound(_))) => results.push(result),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error> {
+        let mut results = Vec::with_capacity(usernames.len());
+        for username in usernames {
+            match self.delete_user(username, None).await {
+                result @ (Ok(()) | Err(ServerError::NotFound(_))) => results.push(result),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(results)
+    }
+
+    /// Summed over the pools of every server the client talks to.
+    fn pool_status(&self) -> Option<PoolStatus> {
+        let open = self.connections.open.load(Ordering::Relaxed);
+        let in_use = self.connections.in_use.load(Ordering::Relaxed);
+        Some(self.waits.status(self.pool_size.into(), in_use, open.saturating_sub(in_use)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::databases::conformance::conformance_tests!(MongoDatabase, "MONGO_URL");
+}
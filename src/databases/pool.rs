@@ -0,0 +1,110 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span, info_span};
+
+use crate::database::PoolStatus;
+
+/// Counts connection checkouts and the time spent waiting for them, for the
+/// pooled backends' `Database::pool_status`.
+#[derive(Clone, Debug, Default)]
+pub struct PoolWaits {
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    checkouts: AtomicU64,
+    wait_us: AtomicU64,
+}
+
+impl PoolWaits {
+    pub fn record(&self, wait: Duration) {
+        self.counters.checkouts.fetch_add(1, Ordering::Relaxed);
+        self.counters.wait_us.fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Awaits a checkout in a `pool.acquire` span, recording how long it took
+    /// whether it succeeded or not.
+    pub async fn time<F: Future>(&self, checkout: F) -> F::Output {
+        let started = Instant::now();
+        let result = checkout.instrument(info_span!("pool.acquire")).await;
+        self.record(started.elapsed());
+        result
+    }
+
+    /// Completes the connection counts read from the pool with the checkouts.
+    pub fn status(&self, max_size: u64, in_use: u64, idle: u64) -> PoolStatus {
+        PoolStatus {
+            max_size,
+            in_use,
+            idle,
+            checkouts: self.counters.checkouts.load(Ordering::Relaxed),
+            wait: Duration::from_micros(self.counters.wait_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// r2d2 reports the time of every checkout, so its pools need no call-site timing.
+impl r2d2::HandleEvent for PoolWaits {
+    fn handle_checkout(&self, event: r2d2::event::CheckoutEvent) {
+        self.record(event.duration());
+    }
+
+    fn handle_timeout(&self, event: r2d2::event::TimeoutEvent) {
+        self.record(event.timeout());
+    }
+}
+
+/// Checks a connection out of an r2d2 pool in a `pool.acquire` span.
+pub fn get<M: r2d2::ManageConnection>(
+    pool: &r2d2::Pool<M>,
+) -> Result<Checkout<r2d2::PooledConnection<M>>, r2d2::Error> {
+    let conn = info_span!("pool.acquire").in_scope(|| pool.get())?;
+    Ok(Checkout::new(conn))
+}
+
+/// A connection that was just checked out, with a `db.query` span timing its
+/// use until it is dropped.
+pub struct Checkout<C> {
+    conn: C,
+    _query: Span,
+}
+
+impl<C> Checkout<C> {
+    pub fn new(conn: C) -> Self {
+        Checkout { conn, _query: info_span!("db.query") }
+    }
+}
+
+impl<C> Deref for Checkout<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.conn
+    }
+}
+
+impl<C> DerefMut for Checkout<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_waits_are_summed() {
+        let waits = PoolWaits::default();
+        waits.record(Duration::from_millis(3));
+        let clone = waits.clone();
+        assert_eq!(clone.time(async { 42 }).await, 42);
+
+        let status = waits.status(10, 2, 5);
+        assert_eq!((status.max_size, status.in_use, status.idle, status.checkouts), (10, 2, 5, 2));
+        assert!(status.wait >= Duration::from_millis(3));
+    }
+}
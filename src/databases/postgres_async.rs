@@ -0,0 +1,267 @@
+use async_trait::async_trait;
+use deadpool_postgres::{Client, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio_postgres::NoTls;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::ToSql;
+
+use crate::config::Config;
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, PoolStatus, UpdateUser, Upserted, User,
+    UserCursor, now_millis,
+};
+use crate::databases::batch;
+use crate::databases::pool::{Checkout, PoolWaits};
+use crate::databases::postgres::{
+    INSERT_USERS, INSERT_USERS_TYPES, Jsonb, SCHEMA, UPDATE_SET, UPDATE_USERS, UPSERT_USER, USER_COLUMNS, UpdateArrays,
+    UpdateParams, user_from_row,
+};
+use crate::err::ServerError;
+
+/// PostgreSQL backend on `tokio-postgres` with a deadpool connection pool.
+///
+/// Shares the `users` table and its statements with `PostgresDatabase`, so both
+/// can be benchmarked against the same data. Statements are prepared once per connection through
+/// deadpool's statement cache.
+#[derive(Clone)]
+pub struct AsyncPostgresDatabase {
+    pool: Pool,
+    waits: PoolWaits,
+}
+
+impl AsyncPostgresDatabase {
+    async fn connection(&self) -> Result<Checkout<Client>, ServerError> {
+        Ok(Checkout::new(self.waits.time(self.pool.get()).await?))
+    }
+}
+
+#[async_trait]
+impl Database for AsyncPostgresDatabase {
+    type Error = ServerError;
+    const NAME: &'static str = "postgres-async";
+
+    async fn init(config: &Config) -> Result<Self, Self::Error> {
+        let pg_config = config.postgres.url.parse::<tokio_postgres::Config>()
+            .map_err(|e| ServerError::Internal(format!("Invalid PostgreSQL URL: {}", e)))?;
+
+        let manager = Manager::from_config(
+            pg_config,
+            NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+
+        // Same size and checkout timeout as the r2d2 pool of `PostgresDatabase`
+        let pool = Pool::builder(manager)
+            .max_size(config.postgres.pool_size as usize)
+            .wait_timeout(Some(Duration::from_millis(config.postgres.connection_timeout_ms)))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map_err(|e| ServerError::Internal(format!("Failed to create PostgreSQL connection pool: {}", e)))?;
+
+        let conn = pool.get().await.map_err(|e| ServerError::from(e).context("Failed to get PostgreSQL connection"))?;
+
+        // Create the users table if it doesn't exist
+        conn.batch_execute(SCHEMA).await.map_err(|e| ServerError::from(e).context("Failed to create PostgreSQL table"))?;
+
+        Ok(AsyncPostgresDatabase { pool, waits: PoolWaits::default() })
+    }
+
+    async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached("INSERT INTO users (username, created_at, updated_at) VALUES ($1, $2, $2);").await?;
+        let result = conn.execute(&statement, &[&user.username, &(now_millis() as i64)]).await;
+        let changed_row = result.map_err(|e| match ServerError::from(e) {
+            ServerError::Conflict(_) => ServerError::Conflict(format!("User already exists: {}", user.username)),
+            e => e.context(&format!("Create user `{}` error", user.username)),
+        })?;
+        if changed_row == 0 {
+            return Err(ServerError::Internal("Error creating user: No rows changed".to_string()));
+        }
+        Ok(format!("User created with username: {}", user.username))
+    }
+
+    async fn get_user(&self, username: String) -> Result<User, Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached(&format!("SELECT {} FROM users WHERE username = $1;", USER_COLUMNS)).await?;
+        let row = conn.query_opt(&statement, &[&username]).await
+            .map_err(|e| ServerError::from(e).context("Get user by username error"))?;
+
+        match row {
+            Some(row) => Ok(user_from_row(&row)),
+            None => Err(ServerError::NotFound(format!("User not found: {}", username))),
+        }
+    }
+
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached(&format!(
+            "UPDATE users SET {} WHERE username = $9 AND ($10::int8 IS NULL OR version = $10) RETURNING version;",
+            UPDATE_SET,
+        )).await?;
+        let params = UpdateParams::new(&update);
+        let expected = expected_version.map(|version| version as i64);
+        match conn.query_opt(&statement, &params.with(&[&username, &expected])).await {
+            Ok(Some(row)) => Ok(row.get::<_, i64>(0) as u64),
+            Ok(None) => Err(unmatched(&conn, &username, expected_version).await),
+            Err(e) => Err(ServerError::from(e).context("Update user by username error")),
+        }
+    }
+
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached(
+            "DELETE FROM users WHERE username = $1 AND ($2::int8 IS NULL OR version = $2);",
+        ).await?;
+        let expected = expected_version.map(|version| version as i64);
+        match conn.execute(&statement, &[&username, &expected]).await {
+            Ok(0) => Err(unmatched(&conn, &username, expected_version).await),
+            Ok(_) => Ok(()),
+            Err(e) => Err(ServerError::from(e).context("Delete user by username error")),
+        }
+    }
+
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error> {
+        let conn = self.connection().await?;
+        // A row version written by an INSERT has no deleting transaction (xmax)
+        // yet, unlike one written by the conflict's UPDATE
+        let statement = conn.prepare_cached(&format!(
+            "{} ON CONFLICT (username) DO UPDATE SET {} RETURNING {}, xmax = 0;",
+            UPSERT_USER, UPDATE_SET, USER_COLUMNS,
+        )).await?;
+        let params = UpdateParams::new(&update);
+        let row = conn.query_one(&statement, &params.with(&[&username])).await
+            .map_err(|e| ServerError::from(e).context(&format!("Upsert user `{}` error", username)))?;
+        Ok(Upserted { user: user_from_row(&row), created: row.get(9) })
+    }
+
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error> {
+        let conn = self.connection().await?;
+        // COPY streams every row in one statement, which fails as a whole on a duplicate
+        let sink = conn.copy_in(INSERT_USERS).await
+            .map_err(|e| ServerError::from(e).context("Insert users error"))?;
+        let writer = BinaryCopyInWriter::new(sink, &INSERT_USERS_TYPES);
+        let mut writer = std::pin::pin!(writer);
+        let now = now_millis() as i64;
+        for user in users {
+            let age = user.age as i32;
+            let attributes = Jsonb(user.attributes);
+            writer.as_mut().write(&[&user.username, &age, &user.email, &user.display_name, &attributes, &now, &now]).await
+                .map_err(|e| ServerError::from(e).context("Insert users error"))?;
+        }
+        writer.finish().await.map_err(|e| ServerError::from(e).context("Insert users error"))?;
+        Ok(())
+    }
+
+    async fn count_users(&self) -> Result<u64, Self::Error> {
+        let conn = self.connection().await?;
+        let row = conn.query_one("SELECT COUNT(*) FROM users;", &[]).await
+            .map_err(|e| ServerError::from(e).context("Count users error"))?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error> {
+        let conn = self.connection().await?;
+        let limit = i64::from(list.limit);
+        let after_id;
+        let (filter, params): (&str, &[&(dyn ToSql + Sync)]) = match &list.cursor {
+            UserCursor::Id(after) => {
+                after_id = i32::try_from(after.unwrap_or(0)).unwrap_or(i32::MAX);
+                ("WHERE id > $1 ORDER BY id LIMIT $2", &[&after_id, &limit])
+            }
+            UserCursor::Username(Some(after)) => ("WHERE username > $1 ORDER BY username LIMIT $2", &[after, &limit]),
+            UserCursor::Username(None) => ("ORDER BY username LIMIT $1", &[&limit]),
+        };
+        let statement = conn.prepare_cached(&format!("SELECT {} FROM users {};", USER_COLUMNS, filter)).await?;
+        let rows = conn.query(&statement, params).await
+            .map_err(|e| ServerError::from(e).context("List users error"))?;
+
+        Ok(rows.iter().map(user_from_row).collect())
+    }
+
+    async fn delete_all_users(&self) -> Result<(), Self::Error> {
+        let conn = self.connection().await?;
+        // Keeps the id sequence going, like deleting the rows one by one would
+        conn.batch_execute("TRUNCATE users;").await
+            .map_err(|e| ServerError::from(e).context("Delete all users error"))
+    }
+
+    // Each batch is one statement over array parameters, returning the
+    // usernames it affected
+
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached(
+            "INSERT INTO users (username, created_at, updated_at) SELECT unnest($1::text[]), $2, $2
+            ON CONFLICT (username) DO NOTHING RETURNING username;",
+        ).await?;
+        let usernames: Vec<String> = users.into_iter().map(|user| user.username).collect();
+        let rows = conn.query(&statement, &[&usernames, &(now_millis() as i64)]).await
+            .map_err(|e| ServerError::from(e).context("Create users error"))?;
+        let created: HashSet<String> = rows.iter().map(|row| row.get(0)).collect();
+        Ok(batch::created(&usernames, &created))
+    }
+
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached(&format!("SELECT {} FROM users WHERE username = ANY($1::text[]);", USER_COLUMNS)).await?;
+        let rows = conn.query(&statement, &[&usernames]).await
+            .map_err(|e| ServerError::from(e).context("Get users error"))?;
+        let users = rows.iter().map(user_from_row).collect();
+        Ok(batch::found(&usernames, users))
+    }
+
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached(UPDATE_USERS).await?;
+        let arrays = UpdateArrays::new(updates);
+        let rows = conn.query(&statement, &arrays.params()).await
+            .map_err(|e| ServerError::from(e).context("Update users error"))?;
+        let matched: HashSet<String> = rows.iter().map(|row| row.get(0)).collect();
+        Ok(batch::matched(arrays.usernames(), &matched))
+    }
+
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error> {
+        let conn = self.connection().await?;
+        let statement = conn.prepare_cached("DELETE FROM users WHERE username = ANY($1::text[]) RETURNING username;").await?;
+        let rows = conn.query(&statement, &[&usernames]).await
+            .map_err(|e| ServerError::from(e).context("Delete users error"))?;
+        let deleted: HashSet<String> = rows.iter().map(|row| row.get(0)).collect();
+        Ok(batch::matched(&usernames, &deleted))
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        let status = self.pool.status();
+        let in_use = status.size - status.available;
+        Some(self.waits.status(status.max_size as u64, in_use as u64, status.available as u64))
+    }
+}
+
+/// Why a write on `username` matched no row: the user is missing, or was found
+/// at another version than `expected_version`.
+async fn unmatched(conn: &Client, username: &str, expected_version: Option<u64>) -> ServerError {
+    let not_found = ServerError::NotFound(format!("User not found: {}", username));
+    if expected_version.is_none() {
+        return not_found;
+    }
+    let exists = conn.query_one("SELECT EXISTS (SELECT 1 FROM users WHERE username = $1);", &[&username]).await;
+    match exists {
+        Ok(row) if row.get(0) => ServerError::PreconditionFailed(format!("User version mismatch: {}", username)),
+        Ok(_) => not_found,
+        Err(e) => ServerError::from(e).context("Check user version error"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::databases::conformance::conformance_tests!(AsyncPostgresDatabase, "POSTGRES_URL");
+}
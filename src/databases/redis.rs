@@ -1,136 +1,972 @@
 use async_trait::async_trait;
-use redis::{AsyncCommands, Client};
-use serde_json;
-use std::sync::Arc;
+use redis::aio::{ConnectionLike, ConnectionManager, MultiplexedConnection};
+use redis::aio::ConnectionManagerConfig;
+use redis::{AsyncCommands, AsyncConnectionConfig, Client, Cmd, Pipeline, RedisFuture, Script, ScriptInvocation, Value};
+use serde::Deserialize;
+use serde_json::Map;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
 
-use crate::database::{CreateUser, Database, UpdateUser, User};
+use crate::config::{Config, RedisConnectionMode};
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, PoolStatus, UpdateUser, Upserted, User,
+    UserCursor, merge_patch, now_millis,
+};
+use crate::databases::pool::{Checkout, PoolWaits};
 use crate::err::ServerError;
 
+/// Where each request gets its Redis connection from, see `RedisConnectionMode`.
+#[derive(Clone)]
+enum Connections {
+    PerRequest(Arc<Client>, AsyncConnectionConfig),
+    Pooled(deadpool_redis::Pool),
+    Multiplexed(Box<ConnectionManager>),
+}
+
+/// A connection handed out by `Connections`, usable with `AsyncCommands`.
+enum Connection {
+    Single(MultiplexedConnection),
+    Pooled(deadpool_redis::Connection),
+    Managed(ConnectionManager),
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Connection::Single(conn) => conn.req_packed_command(cmd),
+            Connection::Pooled(conn) => conn.req_packed_command(cmd),
+            Connection::Managed(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Connection::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            Connection::Pooled(conn) => conn.req_packed_commands(cmd, offset, count),
+            Connection::Managed(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Connection::Single(conn) => conn.get_db(),
+            Connection::Pooled(conn) => conn.get_db(),
+            Connection::Managed(conn) => conn.get_db(),
+        }
+    }
+}
+
+// Every write runs as a Lua script, which Redis executes atomically, so concurrent
+// requests can neither create the same username twice nor write back a user that
+// was deleted or replaced in between. Users are stored as hashes under
+// `user:{username}`, with a field per field of `User`, the attributes as JSON and
+// no `email` or `display_name` field when unset, and `user_id:{id}` mapping ids
+// back to usernames. The sorted sets `users:by_id`, scored by id, and
+// `users:by_name`, all scored 0 so they sort by name, index the usernames for
+// `list_users`, and are the first two KEYS of the scripts writing users. Users
+// stored before the indexes existed are not listed. Ids are reserved from
+// `user:id_counter` before calling the scripts, so that they get the
+// `user_id:{id}` keys they write in KEYS, and like failed SQL inserts, taken
+// usernames use theirs up. The single-user methods call the scripts of the
+// batch ones with one user.
+//
+// Attributes are merged in Rust rather than with Lua's cjson, which rounds large
+// numbers and can't tell `{}` from `[]`: the user is read first and written only
+// at the version read, which is retried if the user changed in between.
+
+/// KEYS: indexes, then the user key and id key of each user. ARGV: the time,
+/// then the username and id of each user. Returns per user 1 if created, 0 if
+/// taken.
+static CREATE_USERS_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local created = {}
+        for i = 1, (#KEYS - 2) / 2 do
+            local key = KEYS[2 * i + 1]
+            if redis.call('EXISTS', key) == 1 then
+                created[i] = 0
+            else
+                local username, id = ARGV[2 * i], ARGV[2 * i + 1]
+                redis.call('HSET', key, 'id', id, 'username', username, 'age', 0, 'attributes', '{}',
+                    'created_at', ARGV[1], 'updated_at', ARGV[1], 'version', 1)
+                redis.call('SET', KEYS[2 * i + 2], username)
+                redis.call('ZADD', KEYS[1], id, username)
+                redis.call('ZADD', KEYS[2], 0, username)
+                created[i] = 1
+            end
+        end
+        return created
+        ",
+    )
+});
+
+/// KEYS: user keys. ARGV: the time, then per user its expected version, empty
+/// for any, the number of fields to set followed by their names and values, and
+/// the number of fields to delete followed by their names. Returns per user its
+/// new version, 0 if missing or -1 if at another version.
+static UPDATE_USERS_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local versions = {}
+        local arg = 2
+        for i = 1, #KEYS do
+            local expected = ARGV[arg]
+            local sets = arg + 2
+            local dels = sets + 2 * tonumber(ARGV[arg + 1]) + 1
+            arg = dels + tonumber(ARGV[dels - 1])
+            local version = redis.call('HGET', KEYS[i], 'version')
+            if not version then
+                versions[i] = 0
+            elseif expected ~= '' and expected ~= version then
+                versions[i] = -1
+            else
+                redis.call('HSET', KEYS[i], 'updated_at', ARGV[1], unpack(ARGV, sets, dels - 2))
+                if arg > dels then
+                    redis.call('HDEL', KEYS[i], unpack(ARGV, dels, arg - 1))
+                end
+                versions[i] = redis.call('HINCRBY', KEYS[i], 'version', 1)
+            end
+        end
+        return versions
+        ",
+    )
+});
+
+/// KEYS: indexes, then the user key and id key of each user. ARGV: expected
+/// version of each user, empty for any, and the id read for it. Returns per
+/// user 1 if deleted, 0 if missing, -1 if at another version or -2 if at
+/// another id, the user having been recreated since its id was read.
+static DELETE_USERS_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local deleted = {}
+        for i = 1, (#KEYS - 2) / 2 do
+            local key, expected = KEYS[2 * i + 1], ARGV[2 * i - 1]
+            local user = redis.call('HMGET', key, 'id', 'username', 'version')
+            if not user[1] then
+                deleted[i] = 0
+            elseif user[1] ~= ARGV[2 * i] then
+                deleted[i] = -2
+            elseif expected ~= '' and expected ~= user[3] then
+                deleted[i] = -1
+            else
+                redis.call('DEL', key, KEYS[2 * i + 2])
+                redis.call('ZREM', KEYS[1], user[2])
+                redis.call('ZREM', KEYS[2], user[2])
+                deleted[i] = 1
+            end
+        end
+        return deleted
+        ",
+    )
+});
+
+/// KEYS: indexes, user key, then the id key of the id to create it with, if
+/// any. ARGV: username, the time, expected version, empty for any or 0 for
+/// none, the id, empty for none, then the fields to set and delete like
+/// `UPDATE_USERS_SCRIPT`. Returns 1 if the user was created, 0 if updated, -1
+/// if at another version or -2 if missing without an id, and the fields of the
+/// user.
+static UPSERT_USER_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local key = KEYS[3]
+        local version = redis.call('HGET', key, 'version')
+        if ARGV[3] ~= '' and ARGV[3] ~= (version or '0') then
+            return { -1, {} }
+        end
+        local created = 0
+        if not version then
+            if ARGV[4] == '' then
+                return { -2, {} }
+            end
+            created = 1
+            redis.call('HSET', key, 'id', ARGV[4], 'username', ARGV[1], 'age', 0, 'attributes', '{}',
+                'created_at', ARGV[2], 'version', 0)
+            redis.call('SET', KEYS[4], ARGV[1])
+            redis.call('ZADD', KEYS[1], ARGV[4], ARGV[1])
+            redis.call('ZADD', KEYS[2], 0, ARGV[1])
+        end
+        local dels = 7 + 2 * tonumber(ARGV[5])
+        redis.call('HSET', key, 'updated_at', ARGV[2], unpack(ARGV, 6, dels - 2))
+        if #ARGV >= dels then
+            redis.call('HDEL', key, unpack(ARGV, dels, #ARGV))
+        end
+        redis.call('HINCRBY', key, 'version', 1)
+        return { created, redis.call('HGETALL', key) }
+        ",
+    )
+});
+
+/// KEYS: indexes, then the user key and id key of each user. ARGV: the time,
+/// then per user its username, its id and the number of its other fields
+/// followed by their names and values. Returns 0 if all were created, otherwise
+/// the 1-based index of the first taken username, the users before it being
+/// kept.
+static INSERT_USERS_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local arg = 2
+        for i = 1, (#KEYS - 2) / 2 do
+            local key = KEYS[2 * i + 1]
+            if redis.call('EXISTS', key) == 1 then
+                return i
+            end
+            local username, id = ARGV[arg], ARGV[arg + 1]
+            local fields = arg + 3
+            arg = fields + 2 * tonumber(ARGV[arg + 2])
+            redis.call('HSET', key, 'id', id, 'username', username, 'created_at', ARGV[1],
+                'updated_at', ARGV[1], 'version', 1, unpack(ARGV, fields, arg - 1))
+            redis.call('SET', KEYS[2 * i + 2], username)
+            redis.call('ZADD', KEYS[1], id, username)
+            redis.call('ZADD', KEYS[2], 0, username)
+        end
+        return 0
+        ",
+    )
+});
+
+/// KEYS: user keys. ARGV: per user the JSON string read from its key and the
+/// number of fields of its hash followed by their names and values. Replaces
+/// each key still holding that JSON with the hash.
+static MIGRATE_USERS_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local arg = 1
+        for i = 1, #KEYS do
+            local json, fields = ARGV[arg], arg + 2
+            arg = fields + 2 * tonumber(ARGV[arg + 1])
+            if redis.call('TYPE', KEYS[i]).ok == 'string' and redis.call('GET', KEYS[i]) == json then
+                redis.call('DEL', KEYS[i])
+                redis.call('HSET', KEYS[i], unpack(ARGV, fields, arg - 1))
+            end
+        end
+        ",
+    )
+});
+
+/// A user as versions before hashes stored it, as JSON. Versions before user
+/// versions stored none.
+#[derive(Deserialize)]
+struct LegacyUser {
+    id: u64,
+    username: String,
+    age: u32,
+    #[serde(default)]
+    version: u64,
+}
+
+/// An invocation of `script` with the indexes as its first KEYS.
+fn with_indexes(script: &Script) -> ScriptInvocation<'_> {
+    let mut invocation = script.key("users:by_id");
+    invocation.key("users:by_name");
+    invocation
+}
+
+/// Tries of a write that reads the user first, to merge attributes or name its
+/// id key, before giving up on a user that keeps changing in between.
+const WRITE_ATTEMPTS: usize = 10;
+
+/// Returned by `apply_updates` and `delete_at` for a user given up on after
+/// `WRITE_ATTEMPTS`.
+const CONTENDED: i64 = -2;
+
+/// The items of a write still to try, retried while their user changed since
+/// it was read, up to `WRITE_ATTEMPTS` tries.
+struct Retries {
+    pending: Vec<usize>,
+    attempts: usize,
+}
+
+impl Retries {
+    fn new(items: usize) -> Self {
+        Retries { pending: (0..items).collect(), attempts: 0 }
+    }
+
+    /// The items to try next, `None` once all settled or the attempts ran out,
+    /// `pending` then holding the items given up on.
+    fn next(&mut self) -> Option<Vec<usize>> {
+        if self.pending.is_empty() || self.attempts == WRITE_ATTEMPTS {
+            return None;
+        }
+        self.attempts += 1;
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    fn retry(&mut self, item: usize) {
+        self.pending.push(item);
+    }
+}
+
+fn contended(username: &str) -> ServerError {
+    ServerError::Conflict(format!("User changed too often to write: {}", username))
+}
+
+/// Users per `INSERT_USERS_SCRIPT` call and per call of the batch scripts, so a
+/// large seed or batch doesn't block Redis in one long script.
+const INSERT_BATCH_SIZE: usize = 1000;
+
 #[derive(Clone)]
 pub struct RedisDatabase {
-    client: Arc<Client>,
+    connections: Connections,
+    /// Checkouts of the `pooled` mode.
+    waits: PoolWaits,
+}
+
+impl RedisDatabase {
+    async fn connection(&self) -> Result<Checkout<Connection>, ServerError> {
+        let conn = match &self.connections {
+            Connections::PerRequest(client, config) => {
+                Connection::Single(client.get_multiplexed_async_connection_with_config(config).await?)
+            }
+            Connections::Pooled(pool) => Connection::Pooled(self.waits.time(pool.get()).await?),
+            // Clones share one multiplexed connection that reconnects on failure
+            Connections::Multiplexed(manager) => Connection::Managed(manager.as_ref().clone()),
+        };
+        Ok(Checkout::new(conn))
+    }
+
+    /// Returns every key matching `pattern`, and of type `kind` if given (Redis
+    /// 6.0), scanning rather than blocking Redis with KEYS.
+    async fn scan_keys(conn: &mut Connection, pattern: &str, kind: Option<&str>) -> Result<Vec<String>, ServerError> {
+        let mut keys = Vec::new();
+        let mut cursor = 0_u64;
+        loop {
+            let mut scan = redis::cmd("SCAN");
+            scan.arg(cursor).arg("MATCH").arg(pattern).arg("COUNT").arg(1000);
+            if let Some(kind) = kind {
+                scan.arg("TYPE").arg(kind);
+            }
+            let (next, batch): (u64, Vec<String>) = scan.query_async(conn).await?;
+            keys.extend(batch);
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+
+    /// Converts the users that versions before hashes stored as JSON strings,
+    /// keeping their version or starting them at 0, without email, display name
+    /// or attributes and with timestamps of 0.
+    async fn migrate_users(&self) -> Result<(), ServerError> {
+        let mut conn = self.connection().await?;
+        let mut keys = Self::scan_keys(&mut conn, "user:*", Some("string")).await?;
+        keys.retain(|key| key != "user:id_counter");
+        for batch in keys.chunks(INSERT_BATCH_SIZE) {
+            let stored: Vec<Option<String>> = redis::cmd("MGET").arg(batch).query_async(&mut *conn).await?;
+            let mut invocation = MIGRATE_USERS_SCRIPT.prepare_invoke();
+            for (key, json) in batch.iter().zip(stored) {
+                // Converted or deleted since the scan
+                let Some(json) = json else {
+                    continue;
+                };
+                let user: LegacyUser = serde_json::from_str(&json)
+                    .map_err(|e| ServerError::Internal(format!("Failed to deserialize {}: {}", key, e)))?;
+                let fields = [
+                    ("id", user.id.to_string()),
+                    ("username", user.username),
+                    ("age", user.age.to_string()),
+                    ("attributes", "{}".to_string()),
+                    ("created_at", "0".to_string()),
+                    ("updated_at", "0".to_string()),
+                    ("version", user.version.to_string()),
+                ];
+                invocation.key(key).arg(&json).arg(fields.len()).arg(&fields[..]);
+            }
+            let _: () = invocation.invoke_async(&mut *conn).await?;
+        }
+        Ok(())
+    }
+
+    /// Reserves `count` ids from `user:id_counter`, returning the first.
+    async fn reserve_ids(conn: &mut Connection, count: usize) -> Result<u64, ServerError> {
+        let last: u64 = conn.incr("user:id_counter", count).await?;
+        Ok(last + 1 - count as u64)
+    }
+
+    /// Deletes each user, at its expected version if any, and returns per user
+    /// what `DELETE_USERS_SCRIPT` does, or `CONTENDED`. Ids are read first to
+    /// name the id keys, and read again for users recreated in between.
+    async fn delete_at(conn: &mut Connection, deletes: &[(&str, Option<u64>)]) -> Result<Vec<i64>, ServerError> {
+        let mut deleted = vec![0; deletes.len()];
+        let mut retries = Retries::new(deletes.len());
+        while let Some(pending) = retries.next() {
+            let mut pipeline = redis::pipe();
+            for &i in &pending {
+                pipeline.hget(format!("user:{}", deletes[i].0), "id");
+            }
+            let ids: Vec<Option<String>> = pipeline.query_async(&mut *conn).await?;
+
+            let mut invocation = with_indexes(&DELETE_USERS_SCRIPT);
+            let mut invoked = Vec::with_capacity(pending.len());
+            for (&i, id) in pending.iter().zip(ids) {
+                // Missing users stay at 0
+                let Some(id) = id else {
+                    continue;
+                };
+                let (username, expected_version) = deletes[i];
+                invocation
+                    .key(format!("user:{}", username))
+                    .key(format!("user_id:{}", id))
+                    .arg(expected_version.map(|version| version.to_string()).unwrap_or_default())
+                    .arg(id);
+                invoked.push(i);
+            }
+            if invoked.is_empty() {
+                continue;
+            }
+
+            let results: Vec<i64> = invocation.invoke_async(&mut *conn).await?;
+            for (i, result) in invoked.into_iter().zip(results) {
+                if result == -2 {
+                    retries.retry(i);
+                } else {
+                    deleted[i] = result;
+                }
+            }
+        }
+        for i in retries.pending {
+            deleted[i] = CONTENDED;
+        }
+        Ok(deleted)
+    }
+
+    /// Each user, `None` if missing, read in one pipeline.
+    async fn read_users(conn: &mut Connection, usernames: &[String]) -> Result<Vec<Option<User>>, ServerError> {
+        if usernames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipeline = redis::pipe();
+        for username in usernames {
+            pipeline.hgetall(format!("user:{}", username));
+        }
+        let users: Vec<HashMap<String, String>> = pipeline.query_async(conn).await?;
+        users.into_iter().map(parse_user).collect()
+    }
+
+    /// The version and attributes of each user, `None` if missing.
+    async fn read_attributes(
+        conn: &mut Connection,
+        usernames: &[&str],
+    ) -> Result<Vec<Option<(String, Attributes)>>, ServerError> {
+        if usernames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipeline = redis::pipe();
+        for username in usernames {
+            pipeline.hget(format!("user:{}", username), &["version", "attributes"]);
+        }
+        let stored: Vec<(Option<String>, Option<String>)> = pipeline.query_async(conn).await?;
+        stored
+            .into_iter()
+            .map(|(version, attributes)| match version {
+                Some(version) => Ok(Some((version, parse_attributes(attributes.as_deref())?))),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Applies each update to its user, at its expected version if any, and
+    /// returns per user what `UPDATE_USERS_SCRIPT` does, or `CONTENDED`.
+    async fn apply_updates(
+        conn: &mut Connection,
+        updates: &[(&str, &UpdateUser, Option<u64>)],
+    ) -> Result<Vec<i64>, ServerError> {
+        let mut versions = vec![0; updates.len()];
+        let mut retries = Retries::new(updates.len());
+        while let Some(pending) = retries.next() {
+            let merging: Vec<&str> = pending
+                .iter()
+                .map(|&i| updates[i])
+                .filter(|(_, update, _)| matches!(update.attributes, Some(Some(_))))
+                .map(|(username, ..)| username)
+                .collect();
+            let mut stored = Self::read_attributes(conn, &merging).await?.into_iter();
+
+            let mut invocation = UPDATE_USERS_SCRIPT.prepare_invoke();
+            invocation.arg(now_millis());
+            let mut invoked = Vec::with_capacity(pending.len());
+            for &i in &pending {
+                let (username, update, expected_version) = updates[i];
+                let expected = expected_version.map(|version| version.to_string());
+                let (expected, attributes) = match &update.attributes {
+                    Some(Some(patch)) => match stored.next().flatten() {
+                        Some((version, mut attributes)) if expected.as_ref().is_none_or(|e| *e == version) => {
+                            merge_patch(&mut attributes, patch);
+                            (version, Some(attributes))
+                        }
+                        Some(_) => {
+                            versions[i] = -1;
+                            continue;
+                        }
+                        None => continue,
+                    },
+                    Some(None) => (expected.unwrap_or_default(), Some(Attributes::new())),
+                    None => (expected.unwrap_or_default(), None),
+                };
+                invocation.key(format!("user:{}", username)).arg(expected);
+                update_args(&mut invocation, update, attributes);
+                invoked.push(i);
+            }
+            if invoked.is_empty() {
+                continue;
+            }
+
+            let results: Vec<i64> = invocation.invoke_async(&mut *conn).await?;
+            for (i, version) in invoked.into_iter().zip(results) {
+                // Merged attributes are written at the version read, so retried if
+                // that changed, unless the caller expected it
+                if version == -1 && updates[i].2.is_none() {
+                    retries.retry(i);
+                } else {
+                    versions[i] = version;
+                }
+            }
+        }
+        for i in retries.pending {
+            versions[i] = CONTENDED;
+        }
+        Ok(versions)
+    }
+}
+
+type Attributes = Map<String, serde_json::Value>;
+
+/// Adds the fields that `update` sets and deletes to the arguments of
+/// `UPDATE_USERS_SCRIPT` or `UPSERT_USER_SCRIPT`, `attributes` being the ones to
+/// store if it changes them.
+fn update_args(invocation: &mut ScriptInvocation, update: &UpdateUser, attributes: Option<Attributes>) {
+    let mut sets = Vec::new();
+    let mut dels = Vec::new();
+    if let Some(age) = update.age {
+        sets.push(("age", age.to_string()));
+    }
+    for (field, value) in [("email", &update.email), ("display_name", &update.display_name)] {
+        match value {
+            Some(Some(value)) => sets.push((field, value.clone())),
+            Some(None) => dels.push(field),
+            None => {}
+        }
+    }
+    if let Some(attributes) = attributes {
+        sets.push(("attributes", serde_json::Value::Object(attributes).to_string()));
+    }
+    invocation.arg(sets.len()).arg(sets).arg(dels.len()).arg(dels);
+}
+
+/// The attributes stored as `json`, none if there is no such field.
+fn parse_attributes(json: Option<&str>) -> Result<Attributes, ServerError> {
+    json.map_or(Ok(Attributes::new()), serde_json::from_str)
+        .map_err(|e| ServerError::Internal(format!("Failed to deserialize user attributes: {}", e)))
+}
+
+/// Reads a user from the fields of its hash, `None` if it has none, being missing.
+fn parse_user(mut fields: HashMap<String, String>) -> Result<Option<User>, ServerError> {
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let invalid = |field: &str| ServerError::Internal(format!("Failed to deserialize user: invalid {}", field));
+    let number = |field: &str| -> Result<u64, ServerError> {
+        fields.get(field).and_then(|value| value.parse().ok()).ok_or_else(|| invalid(field))
+    };
+    let id = number("id")?;
+    let age = number("age")?.try_into().map_err(|_| invalid("age"))?;
+    let created_at = number("created_at")?;
+    let updated_at = number("updated_at")?;
+    let version = number("version")?;
+    let attributes = parse_attributes(fields.get("attributes").map(String::as_str))?;
+    Ok(Some(User {
+        id,
+        username: fields.remove("username").ok_or_else(|| invalid("username"))?,
+        age,
+        email: fields.remove("email"),
+        display_name: fields.remove("display_name"),
+        attributes,
+        created_at,
+        updated_at,
+        version,
+    }))
 }
 
 #[async_trait]
 impl Database for RedisDatabase {
     type Error = ServerError;
+    const NAME: &'static str = "redis";
+
+    async fn init(config: &Config) -> Result<Self, Self::Error> {
+        let redis_url = config.redis.url.as_str();
+        let connection_timeout = Duration::from_millis(config.redis.connection_timeout_ms);
 
-    async fn init() -> Result<Self, Self::Error> {
-        // Use environment variable or default to localhost for connection
-        let redis_url = std::env::var("REDIS_URL")
-            .unwrap_or_else(|_| "redis://localhost:6379".to_string());
-        
         let client = Client::open(redis_url)
-            .map_err(|e| ServerError::new(&format!("Failed to create Redis client: {}", e)))?;
+            .map_err(|e| ServerError::from(e).context("Failed to create Redis client"))?;
+
+        let connections = match config.redis.mode {
+            RedisConnectionMode::PerRequest => {
+                let connection_config = AsyncConnectionConfig::new().set_connection_timeout(connection_timeout);
+                Connections::PerRequest(Arc::new(client), connection_config)
+            }
+            RedisConnectionMode::Pooled => {
+                let manager = deadpool_redis::Manager::new(redis_url)
+                    .map_err(|e| ServerError::from(e).context("Failed to create Redis client"))?;
+                let pool = deadpool_redis::Pool::builder(manager)
+                    .max_size(config.redis.pool_size as usize)
+                    .wait_timeout(Some(connection_timeout))
+                    .create_timeout(Some(connection_timeout))
+                    .runtime(deadpool_redis::Runtime::Tokio1)
+                    .build()
+                    .map_err(|e| ServerError::Internal(format!("Failed to create Redis connection pool: {}", e)))?;
+                Connections::Pooled(pool)
+            }
+            RedisConnectionMode::Multiplexed => {
+                let manager_config = ConnectionManagerConfig::new().set_connection_timeout(connection_timeout);
+                let manager = ConnectionManager::new_with_config(client, manager_config).await
+                    .map_err(|e| ServerError::from(e).context("Failed to get Redis connection"))?;
+                Connections::Multiplexed(Box::new(manager))
+            }
+        };
 
-        Ok(RedisDatabase {
-            client: Arc::new(client),
-        })
+        let db = RedisDatabase { connections, waits: PoolWaits::default() };
+        db.migrate_users().await
+            .map_err(|e| e.context("Failed to convert Redis users to hashes"))?;
+        Ok(db)
     }
 
     async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error> {
-        let mut conn = self.client.get_async_connection().await
-            .map_err(|e| ServerError::new(&format!("Failed to get Redis connection: {}", e)))?;
-        
-        // Check if user already exists
-        let exists: bool = conn.exists(&format!("user:{}", user.username)).await
-            .map_err(|e| ServerError::new(&format!("Failed to check user existence: {}", e)))?;
-        
-        if exists {
-            return Err(format!("User already exists: {}", user.username).into());
-        }
-        
-        // Generate a simple ID (in a real system you'd use a proper ID generator)
-        let id: u64 = conn.incr("user:id_counter", 1).await
-            .map_err(|e| ServerError::new(&format!("Failed to generate user ID: {}", e)))?;
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
         
-        let user_data = User {
-            id,
-            username: user.username.clone(),
-            age: 0,
-        };
-        
-        let user_json = serde_json::to_string(&user_data)
-            .map_err(|e| ServerError::new(&format!("Failed to serialize user: {}", e)))?;
-        
-        // Store user data with username as key
-        let _: () = conn.set(&format!("user:{}", user.username), &user_json).await
-            .map_err(|e| ServerError::new(&format!("Failed to store user: {}", e)))?;
-        
-        // Also store username with id as key for potential id-based lookups
-        let _: () = conn.set(&format!("user_id:{}", id), &user.username).await
-            .map_err(|e| ServerError::new(&format!("Failed to store user ID mapping: {}", e)))?;
+        let id = Self::reserve_ids(&mut conn, 1).await
+            .map_err(|e| e.context("Failed to store user"))?;
+        let created: Vec<bool> = with_indexes(&CREATE_USERS_SCRIPT)
+            .key(format!("user:{}", user.username))
+            .key(format!("user_id:{}", id))
+            .arg(now_millis())
+            .arg(&user.username)
+            .arg(id)
+            .invoke_async(&mut *conn)
+            .await
+            .map_err(|e| ServerError::from(e).context("Failed to store user"))?;
 
+        if created != [true] {
+            return Err(ServerError::Conflict(format!("User already exists: {}", user.username)));
+        }
         Ok(format!("User created with username: {}", user.username))
     }
 
     async fn get_user(&self, username: String) -> Result<User, Self::Error> {
-        let mut conn = self.client.get_async_connection().await
-            .map_err(|e| ServerError::new(&format!("Failed to get Redis connection: {}", e)))?;
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
         
-        let user_json: Option<String> = conn.get(&format!("user:{}", username)).await
-            .map_err(|e| ServerError::new(&format!("Failed to get user: {}", e)))?;
+        let fields: HashMap<String, String> = conn.hgetall(format!("user:{}", username)).await
+            .map_err(|e| ServerError::from(e).context("Failed to get user"))?;
         
-        match user_json {
-            Some(json) => {
-                let user: User = serde_json::from_str(&json)
-                    .map_err(|e| ServerError::new(&format!("Failed to deserialize user: {}", e)))?;
-                Ok(user)
-            },
-            None => Err(format!("User not found: {}", username).into()),
-        }
+        parse_user(fields)?.ok_or_else(|| ServerError::NotFound(format!("User not found: {}", username)))
     }
 
-    async fn update_user(&self, username: String, update: UpdateUser) -> Result<(), Self::Error> {
-        let mut conn = self.client.get_async_connection().await
-            .map_err(|e| ServerError::new(&format!("Failed to get Redis connection: {}", e)))?;
-        
-        // Get existing user
-        let user_json: Option<String> = conn.get(&format!("user:{}", username)).await
-            .map_err(|e| ServerError::new(&format!("Failed to get user: {}", e)))?;
-        
-        match user_json {
-            Some(json) => {
-                let mut user: User = serde_json::from_str(&json)
-                    .map_err(|e| ServerError::new(&format!("Failed to deserialize user: {}", e)))?;
-                
-                user.age = update.age;
-                
-                let updated_json = serde_json::to_string(&user)
-                    .map_err(|e| ServerError::new(&format!("Failed to serialize updated user: {}", e)))?;
-                
-                let _: () = conn.set(&format!("user:{}", username), &updated_json).await
-                    .map_err(|e| ServerError::new(&format!("Failed to update user: {}", e)))?;
-                
-                Ok(())
-            },
-            None => Err(format!("User not found: {}", username).into()),
-        }
-    }
-
-    async fn delete_user(&self, username: String) -> Result<(), Self::Error> {
-        let mut conn = self.client.get_async_connection().await
-            .map_err(|e| ServerError::new(&format!("Failed to get Redis connection: {}", e)))?;
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
         
-        // First get the user to find their ID
-        let user_json: Option<String> = conn.get(&format!("user:{}", username)).await
-            .map_err(|e| ServerError::new(&format!("Failed to get user: {}", e)))?;
+        let versions = Self::apply_updates(&mut conn, &[(&username, &update, expected_version)]).await
+            .map_err(|e| e.context("Failed to update user"))?;
+
+        match versions[..] {
+            [-1] => Err(ServerError::PreconditionFailed(format!("User version mismatch: {}", username))),
+            [CONTENDED] => Err(contended(&username)),
+            [version] if version > 0 => Ok(version as u64),
+            _ => Err(ServerError::NotFound(format!("User not found: {}", username))),
+        }
+    }
+
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
         
-        match user_json {
-            Some(json) => {
-                let user: User = serde_json::from_str(&json)
-                    .map_err(|e| ServerError::new(&format!("Failed to deserialize user: {}", e)))?;
-                
-                // Delete both the user data and the ID mapping
-                let _: () = conn.del(&format!("user:{}", username)).await
-                    .map_err(|e| ServerError::new(&format!("Failed to delete user: {}", e)))?;
-                
-                let _: () = conn.del(&format!("user_id:{}", user.id)).await
-                    .map_err(|e| ServerError::new(&format!("Failed to delete user ID mapping: {}", e)))?;
-                
-                Ok(())
-            },
-            None => Err(format!("User not found: {}", username).into()),
-        }
-    }
-}
\ No newline at end of file
+        let deleted = Self::delete_at(&mut conn, &[(&username, expected_version)]).await
+            .map_err(|e| e.context("Failed to delete user"))?;
+
+        match deleted[..] {
+            [1] => Ok(()),
+            [-1] => Err(ServerError::PreconditionFailed(format!("User version mismatch: {}", username))),
+            [CONTENDED] => Err(contended(&username)),
+            _ => Err(ServerError::NotFound(format!("User not found: {}", username))),
+        }
+    }
+
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        let key = format!("user:{}", username);
+        // Reserved once the user turns out missing, so updates don't use ids up
+        let mut id: Option<u64> = None;
+        let mut retries = Retries::new(1);
+        while retries.next().is_some() {
+            // Merged attributes are written at the version read, 0 for a missing user
+            let (expected, attributes) = match &update.attributes {
+                Some(Some(patch)) => {
+                    let stored = Self::read_attributes(&mut conn, &[&username]).await
+                        .map_err(|e| e.context("Failed to upsert user"))?;
+                    let (version, mut attributes) = stored.into_iter().flatten().next()
+                        .unwrap_or_else(|| ("0".to_string(), Attributes::new()));
+                    merge_patch(&mut attributes, patch);
+                    (version, Some(attributes))
+                }
+                Some(None) => (String::new(), Some(Attributes::new())),
+                None => (String::new(), None),
+            };
+            let mut invocation = with_indexes(&UPSERT_USER_SCRIPT);
+            invocation.key(&key);
+            if let Some(id) = id {
+                invocation.key(format!("user_id:{}", id));
+            }
+            invocation
+                .arg(&username)
+                .arg(now_millis())
+                .arg(expected)
+                .arg(id.map(|id| id.to_string()).unwrap_or_default());
+            update_args(&mut invocation, &update, attributes);
+            let (created, fields): (i64, HashMap<String, String>) = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(|e| ServerError::from(e).context("Failed to upsert user"))?;
+            // Changed since its attributes were read
+            if created == -1 {
+                retries.retry(0);
+                continue;
+            }
+            if created == -2 {
+                id = Some(Self::reserve_ids(&mut conn, 1).await.map_err(|e| e.context("Failed to upsert user"))?);
+                retries.retry(0);
+                continue;
+            }
+
+            let user = parse_user(fields)?
+                .ok_or_else(|| ServerError::Internal(format!("Upserted user not found: {}", username)))?;
+            return Ok(Upserted { user, created: created == 1 });
+        }
+        Err(contended(&username))
+    }
+
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        for batch in users.chunks(INSERT_BATCH_SIZE) {
+            let first_id = Self::reserve_ids(&mut conn, batch.len()).await
+                .map_err(|e| e.context("Failed to store users"))?;
+            let mut invocation = with_indexes(&INSERT_USERS_SCRIPT);
+            invocation.arg(now_millis());
+            for (user, id) in batch.iter().zip(first_id..) {
+                let mut fields = vec![
+                    ("age", user.age.to_string()),
+                    ("attributes", serde_json::Value::Object(user.attributes.clone()).to_string()),
+                ];
+                fields.extend(user.email.clone().map(|email| ("email", email)));
+                fields.extend(user.display_name.clone().map(|display_name| ("display_name", display_name)));
+                invocation
+                    .key(format!("user:{}", user.username))
+                    .key(format!("user_id:{}", id))
+                    .arg(&user.username)
+                    .arg(id)
+                    .arg(fields.len())
+                    .arg(fields);
+            }
+            let taken: usize = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(|e| ServerError::from(e).context("Failed to store users"))?;
+            if taken > 0 {
+                return Err(ServerError::Conflict(format!("User already exists: {}", batch[taken - 1].username)));
+            }
+        }
+        Ok(())
+    }
+
+    async fn count_users(&self) -> Result<u64, Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+        // One `user_id:{id}` key per user, unlike `user:*` which also matches the counter
+        let keys = Self::scan_keys(&mut conn, "user_id:*", None).await
+            .map_err(|e| e.context("Failed to count users"))?;
+        Ok(keys.len() as u64)
+    }
+
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        // Exclusive range starts, the cursor being the last user already listed
+        let (index, command, start, end) = match &list.cursor {
+            UserCursor::Id(after) => {
+                let start = after.map_or("-inf".to_string(), |id| format!("({}", id));
+                ("users:by_id", "ZRANGEBYSCORE", start, "+inf")
+            }
+            UserCursor::Username(after) => {
+                let start = after.as_ref().map_or("-".to_string(), |username| format!("({}", username));
+                ("users:by_name", "ZRANGEBYLEX", start, "+")
+            }
+        };
+        let usernames: Vec<String> = redis::cmd(command)
+            .arg(index)
+            .arg(start)
+            .arg(end)
+            .arg("LIMIT")
+            .arg(0)
+            .arg(list.limit)
+            .query_async(&mut *conn)
+            .await
+            .map_err(|e| ServerError::from(e).context("Failed to list users"))?;
+
+        // Users deleted since the range was read are left out
+        let users = Self::read_users(&mut conn, &usernames).await
+            .map_err(|e| e.context("Failed to list users"))?;
+        Ok(users.into_iter().flatten().collect())
+    }
+
+    async fn delete_all_users(&self) -> Result<(), Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        let mut keys = Self::scan_keys(&mut conn, "user:*", None).await
+            .map_err(|e| e.context("Failed to delete all users"))?;
+        // Ids keep increasing like the SQL auto-increment columns
+        keys.retain(|key| key != "user:id_counter");
+        keys.extend(Self::scan_keys(&mut conn, "user_id:*", None).await
+            .map_err(|e| e.context("Failed to delete all users"))?);
+        keys.extend(["users:by_id".to_string(), "users:by_name".to_string()]);
+
+        for batch in keys.chunks(INSERT_BATCH_SIZE) {
+            let _: () = conn.del(batch).await
+                .map_err(|e| ServerError::from(e).context("Failed to delete all users"))?;
+        }
+        Ok(())
+    }
+
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        let mut results = Vec::with_capacity(users.len());
+        for batch in users.chunks(INSERT_BATCH_SIZE) {
+            let first_id = Self::reserve_ids(&mut conn, batch.len()).await
+                .map_err(|e| e.context("Failed to store users"))?;
+            let mut invocation = with_indexes(&CREATE_USERS_SCRIPT);
+            invocation.arg(now_millis());
+            for (user, id) in batch.iter().zip(first_id..) {
+                invocation.key(format!("user:{}", user.username)).key(format!("user_id:{}", id)).arg(&user.username).arg(id);
+            }
+            let created: Vec<bool> = invocation
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(|e| ServerError::from(e).context("Failed to store users"))?;
+            results.extend(batch.iter().zip(created).map(|(user, created)| {
+                if created {
+                    Ok(())
+                } else {
+                    Err(ServerError::Conflict(format!("User already exists: {}", user.username)))
+                }
+            }));
+        }
+        Ok(results)
+    }
+
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        let mut results = Vec::with_capacity(usernames.len());
+        for batch in usernames.chunks(INSERT_BATCH_SIZE) {
+            let users = Self::read_users(&mut conn, batch).await
+                .map_err(|e| e.context("Failed to get users"))?;
+            for (username, user) in batch.iter().zip(users) {
+                results.push(user.ok_or_else(|| ServerError::NotFound(format!("User not found: {}", username))));
+            }
+        }
+        Ok(results)
+    }
+
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        let mut results = Vec::with_capacity(updates.len());
+        for batch in updates.chunks(INSERT_BATCH_SIZE) {
+            let updates: Vec<_> = batch.iter().map(|item| (item.username.as_str(), &item.update, None)).collect();
+            let versions = Self::apply_updates(&mut conn, &updates).await
+                .map_err(|e| e.context("Failed to update users"))?;
+            results.extend(batch.iter().zip(versions).map(|(item, version)| match version {
+                CONTENDED => Err(contended(&item.username)),
+                version if version > 0 => Ok(()),
+                _ => Err(ServerError::NotFound(format!("User not found: {}", item.username))),
+            }));
+        }
+        Ok(results)
+    }
+
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error> {
+        let mut conn = self.connection().await
+            .map_err(|e| e.context("Failed to get Redis connection"))?;
+
+        let mut results = Vec::with_capacity(usernames.len());
+        for batch in usernames.chunks(INSERT_BATCH_SIZE) {
+            let deletes: Vec<_> = batch.iter().map(|username| (username.as_str(), None)).collect();
+            let deleted = Self::delete_at(&mut conn, &deletes).await
+                .map_err(|e| e.context("Failed to delete users"))?;
+            results.extend(batch.iter().zip(deleted).map(|(username, deleted)| match deleted {
+                1 => Ok(()),
+                CONTENDED => Err(contended(username)),
+                _ => Err(ServerError::NotFound(format!("User not found: {}", username))),
+            }));
+        }
+        Ok(results)
+    }
+
+    /// Only the `pooled` mode has a pool, the others open or share connections.
+    fn pool_status(&self) -> Option<PoolStatus> {
+        let Connections::Pooled(pool) = &self.connections else {
+            return None;
+        };
+        let status = pool.status();
+        let in_use = status.size - status.available;
+        Some(self.waits.status(status.max_size as u64, in_use as u64, status.available as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::databases::conformance::conformance_tests!(RedisDatabase, "REDIS_URL");
+
+    #[test]
+    fn test_retries_give_up() {
+        let mut retries = Retries::new(3);
+        assert_eq!(retries.next(), Some(vec![0, 1, 2]));
+        retries.retry(1);
+        for _ in 1..WRITE_ATTEMPTS {
+            assert_eq!(retries.next(), Some(vec![1]));
+            retries.retry(1);
+        }
+        assert_eq!(retries.next(), None);
+        assert_eq!(retries.pending, [1]);
+
+        // Nothing is given up on once every item settled
+        let mut retries = Retries::new(2);
+        assert_eq!(retries.next(), Some(vec![0, 1]));
+        assert_eq!(retries.next(), None);
+        assert!(retries.pending.is_empty());
+    }
+}
@@ -1,109 +1,478 @@
 use async_trait::async_trait;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
+use rusqlite::{OptionalExtension, ToSql, TransactionBehavior, params, params_from_iter};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::database::{CreateUser, Database, UpdateUser, User};
+use crate::config::Config;
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, PoolStatus, UpdateUser, Upserted, User,
+    UserCursor, now_millis,
+};
+use crate::databases::blocking::BlockingExecutor;
+use crate::databases::pool::{self, PoolWaits};
 use crate::err::ServerError;
 
+/// Columns added to `users` since its first version, each with the statement
+/// adding it to a table created before. Existing users get version 1, no
+/// attributes and timestamps of 0.
+const ADDED_COLUMNS: [(&str, &str); 6] = [
+    ("version", "ALTER TABLE users ADD COLUMN version INTEGER NOT NULL DEFAULT 1;"),
+    ("email", "ALTER TABLE users ADD COLUMN email TEXT;"),
+    ("display_name", "ALTER TABLE users ADD COLUMN display_name TEXT;"),
+    ("attributes", "ALTER TABLE users ADD COLUMN attributes TEXT NOT NULL DEFAULT '{}';"),
+    ("created_at", "ALTER TABLE users ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;"),
+    ("updated_at", "ALTER TABLE users ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;"),
+];
+
+/// Columns read by `user_from_row`, in its order.
+const USER_COLUMNS: &str = "id, username, age, email, display_name, attributes, created_at, updated_at, version";
+
+/// Assignments applying an `UpdateUser`, whose values `update_params` binds to
+/// `?1` to `?8`. `json_patch` is SQLite's RFC 7396 merge.
+const UPDATE_SET: &str = "age = COALESCE(?1, age),
+    email = CASE WHEN ?2 THEN ?3 ELSE email END,
+    display_name = CASE WHEN ?4 THEN ?5 ELSE display_name END,
+    attributes = json_patch(CASE WHEN ?6 THEN '{}' ELSE attributes END, ?7),
+    updated_at = ?8, version = version + 1";
+
 #[derive(Clone)]
 pub struct SqliteDatabase {
     pool: Arc<Pool<SqliteConnectionManager>>,
+    executor: BlockingExecutor,
+    waits: PoolWaits,
 }
 
 #[async_trait]
 impl Database for SqliteDatabase {
     type Error = ServerError;
+    const NAME: &'static str = "sqlite";
+
+    async fn init(config: &Config) -> Result<Self, Self::Error> {
+        let sqlite = config.sqlite.clone();
+
+        // Every `:memory:` connection is a separate database, so the pool holds a single one
+        let (manager, pool_size) = if sqlite.path == ":memory:" {
+            (SqliteConnectionManager::memory(), 1)
+        } else {
+            (SqliteConnectionManager::file(&sqlite.path), sqlite.pool_size)
+        };
+        let executor = BlockingExecutor::from_config(&config.executor, pool_size as usize);
+        let waits = PoolWaits::default();
+        let event_handler = Box::new(waits.clone());
+
+        let pool = executor.run(move || {
+            let pool = r2d2::Pool::builder()
+                .max_size(pool_size)
+                .connection_timeout(Duration::from_millis(sqlite.connection_timeout_ms))
+                .event_handler(event_handler)
+                .build(manager.with_init(move |c| {
+                    c.busy_timeout(Duration::from_millis(sqlite.busy_timeout_ms))?;
+                    c.pragma_update(None, "foreign_keys", sqlite.foreign_keys)?;
+                    c.pragma_update(None, "journal_mode", &sqlite.journal_mode)?;
+                    c.pragma_update(None, "synchronous", &sqlite.synchronous)?;
+                    Ok(())
+                }))
+                .map_err(|e| ServerError::Unavailable(format!("Failed to create connection pool: {}", e)))?;
 
-    async fn init() -> Result<Self, Self::Error> {
-        #[cfg(not(test))]
-        let manager = SqliteConnectionManager::file("my_database.db");
-        #[cfg(test)]
-        let manager = SqliteConnectionManager::memory();
-
-        let pool = r2d2::Pool::builder()
-            .build(manager.with_init(|c| {
-                c.pragma_update(None, "foreign_keys", "ON")?;
-                c.pragma_update(None, "journal_mode", "WAL2")?;
-                c.pragma_update(None, "synchronous", "NORMAL")?;
-                Ok(())
-            }))
-            .map_err(|e| ServerError::new(&format!("Failed to create connection pool: {}", e)))?;
-
-        let conn = pool.get().map_err(|e| ServerError::new(&format!("Failed to get connection: {}", e)))?;
-        
-        // Create the users table if it doesn't exist
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                username TEXT NOT NULL UNIQUE,
-                age INTEGER DEFAULT 0
-            );",
-            params![],
-        ).map_err(|e| ServerError::new(&format!("Failed to create table: {}", e)))?;
-        
-        // Create index on username for faster lookups
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_username ON users (username);",
-            params![],
-        ).map_err(|e| ServerError::new(&format!("Failed to create index: {}", e)))?;
+            let conn = pool.get().map_err(|e| ServerError::from(e).context("Failed to get connection"))?;
+            
+            // Create the users table if it doesn't exist
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS users (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    username TEXT NOT NULL UNIQUE,
+                    age INTEGER DEFAULT 0,
+                    email TEXT,
+                    display_name TEXT,
+                    attributes TEXT NOT NULL DEFAULT '{}',
+                    created_at INTEGER NOT NULL,
+                    updated_at INTEGER NOT NULL,
+                    version INTEGER NOT NULL DEFAULT 1
+                );",
+                params![],
+            ).map_err(|e| ServerError::from(e).context("Failed to create table"))?;
+
+            let columns = conn.prepare("SELECT name FROM pragma_table_info('users');")
+                .and_then(|mut statement| statement.query_map([], |row| row.get(0))?.collect::<Result<Vec<String>, _>>())
+                .map_err(|e| ServerError::from(e).context("Failed to read table columns"))?;
+            for (column, statement) in ADDED_COLUMNS {
+                if !columns.iter().any(|existing| existing == column) {
+                    conn.execute(statement, params![])
+                        .map_err(|e| ServerError::from(e).context(&format!("Failed to add column {}", column)))?;
+                }
+            }
+            
+            // Create index on username for faster lookups
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS idx_username ON users (username);",
+                params![],
+            ).map_err(|e| ServerError::from(e).context("Failed to create index"))?;
+
+            Ok(pool)
+        }).await?;
 
         Ok(SqliteDatabase {
             pool: Arc::new(pool),
+            executor,
+            waits,
         })
     }
 
     async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error> {
-        let conn = self.pool.get()?;
-        let result = conn.execute(
-            "INSERT INTO users (username) VALUES (?);",
-            params![user.username],
-        );
-        let changed_row = result.map_err(|e| format!("Create user `{}` error: {}", user.username, e))?;
-        if changed_row == 0 {
-            return Err(format!("Error creating user: No rows changed").into());
-        }
-        Ok(format!("User created with username: {}", user.username))
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            let result = conn.execute(
+                "INSERT INTO users (username, created_at, updated_at) VALUES (?1, ?2, ?2);",
+                params![user.username, now_millis()],
+            );
+            let changed_row = result.map_err(|e| match ServerError::from(e) {
+                ServerError::Conflict(_) => ServerError::Conflict(format!("User already exists: {}", user.username)),
+                e => e.context(&format!("Create user `{}` error", user.username)),
+            })?;
+            if changed_row == 0 {
+                return Err(ServerError::Internal("Error creating user: No rows changed".to_string()));
+            }
+            Ok(format!("User created with username: {}", user.username))
+        }).await
     }
 
     async fn get_user(&self, username: String) -> Result<User, Self::Error> {
-        let conn = self.pool.get()?;
-        let result = conn.query_one(
-            "SELECT id, username, age FROM users WHERE username = ?;",
-            params![username],
-            |row| {
-                Ok(User {
-                    id: row.get(0)?,
-                    username: row.get(1)?,
-                    age: row.get(2)?,
-                })
-            },
-        );
-        match result {
-            Ok(user) => Ok(user),
-            Err(e) => Err(format!("Get user by username error: {}", e).into()),
-        }
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            let result = conn.query_one(
+                &format!("SELECT {} FROM users WHERE username = ?;", USER_COLUMNS),
+                params![username],
+                user_from_row,
+            );
+            match result {
+                Ok(user) => Ok(user),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Err(ServerError::NotFound(format!("User not found: {}", username))),
+                Err(e) => Err(ServerError::from(e).context("Get user by username error")),
+            }
+        }).await
     }
 
-    async fn update_user(&self, username: String, update: UpdateUser) -> Result<(), Self::Error> {
-        let conn = self.pool.get()?;
-        let statement = conn.execute(
-            "UPDATE users SET age = ? WHERE username = ?;",
-            params![update.age, username],
-        );
-        match statement {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Update user by username error: {}", e).into()),
-        }
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            let mut params = update_params(&update);
+            params.extend([Box::new(username.clone()) as Box<dyn ToSql>, Box::new(expected_version)]);
+            let version = conn.query_row(
+                &format!("UPDATE users SET {} WHERE username = ?9 AND (?10 IS NULL OR version = ?10) RETURNING version;", UPDATE_SET),
+                params_from_iter(&params),
+                |row| row.get(0),
+            );
+            match version.optional() {
+                Ok(Some(version)) => Ok(version),
+                Ok(None) => Err(unmatched(&conn, &username, expected_version)),
+                Err(e) => Err(ServerError::from(e).context("Update user by username error")),
+            }
+        }).await
+    }
+
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            let statement = conn.execute(
+                "DELETE FROM users WHERE username = ?1 AND (?2 IS NULL OR version = ?2);",
+                params![username, expected_version],
+            );
+            match statement {
+                Ok(0) => Err(unmatched(&conn, &username, expected_version)),
+                Ok(_) => Ok(()),
+                Err(e) => Err(ServerError::from(e).context("Delete user by username error")),
+            }
+        }).await
+    }
+
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let mut conn = pool::get(&pool)?;
+            // The write lock taken up front keeps the user from appearing or going
+            // away between the check and the upsert
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(|e| ServerError::from(e).context("Upsert user error"))?;
+            let existed: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM users WHERE username = ?);",
+                params![username],
+                |row| row.get(0),
+            ).map_err(|e| ServerError::from(e).context("Upsert user error"))?;
+            let mut params = update_params(&update);
+            params.push(Box::new(username.clone()));
+            // A new user gets the values of the update over those of `create_user`
+            let user = tx.query_row(
+                &format!(
+                    "INSERT INTO users (username, age, email, display_name, attributes, created_at, updated_at)
+                    VALUES (?9, COALESCE(?1, 0), ?3, ?5, json_patch('{{}}', ?7), ?8, ?8)
+                    ON CONFLICT (username) DO UPDATE SET {}
+                    RETURNING {};",
+                    UPDATE_SET, USER_COLUMNS,
+                ),
+                params_from_iter(&params),
+                user_from_row,
+            ).map_err(|e| ServerError::from(e).context(&format!("Upsert user `{}` error", username)))?;
+            tx.commit().map_err(|e| ServerError::from(e).context("Upsert user error"))?;
+            Ok(Upserted { user, created: !existed })
+        }).await
+    }
+
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let mut conn = pool::get(&pool)?;
+            // One transaction and one prepared statement, so rows are not synced one by one
+            let tx = conn.transaction().map_err(|e| ServerError::from(e).context("Insert users error"))?;
+            {
+                let mut statement = tx.prepare(
+                    "INSERT INTO users (username, age, email, display_name, attributes, created_at, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6);",
+                ).map_err(|e| ServerError::from(e).context("Insert users error"))?;
+                let now = now_millis();
+                for user in &users {
+                    let attributes = serde_json::Value::Object(user.attributes.clone()).to_string();
+                    let values = params![user.username, user.age, user.email, user.display_name, attributes, now];
+                    statement.execute(values).map_err(|e| match ServerError::from(e) {
+                        ServerError::Conflict(_) => ServerError::Conflict(format!("User already exists: {}", user.username)),
+                        e => e.context("Insert users error"),
+                    })?;
+                }
+            }
+            tx.commit().map_err(|e| ServerError::from(e).context("Insert users error"))
+        }).await
+    }
+
+    async fn count_users(&self) -> Result<u64, Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            conn.query_one("SELECT COUNT(*) FROM users;", params![], |row| row.get(0))
+                .map_err(|e| ServerError::from(e).context("Count users error"))
+        }).await
+    }
+
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            let query = |filter: &str, params: &[&dyn ToSql]| {
+                let mut statement = conn.prepare_cached(&format!("SELECT {} FROM users {};", USER_COLUMNS, filter))?;
+                let users = statement.query_map(params, user_from_row)?;
+                users.collect::<rusqlite::Result<Vec<_>>>()
+            };
+            let users = match &list.cursor {
+                UserCursor::Id(after) => query(
+                    "WHERE id > ? ORDER BY id LIMIT ?",
+                    params![i64::try_from(after.unwrap_or(0)).unwrap_or(i64::MAX), list.limit],
+                ),
+                UserCursor::Username(Some(after)) => query(
+                    "WHERE username > ? ORDER BY username LIMIT ?",
+                    params![after, list.limit],
+                ),
+                UserCursor::Username(None) => query("ORDER BY username LIMIT ?", params![list.limit]),
+            };
+            users.map_err(|e| ServerError::from(e).context("List users error"))
+        }).await
     }
 
-    async fn delete_user(&self, username: String) -> Result<(), Self::Error> {
-        let conn = self.pool.get()?;
-        let statement = conn.execute("DELETE FROM users WHERE username = ?;", params![username]);
-        match statement {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Delete user by username error: {}", e).into()),
+    async fn delete_all_users(&self) -> Result<(), Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            conn.execute("DELETE FROM users;", params![])
+                .map_err(|e| ServerError::from(e).context("Delete all users error"))?;
+            Ok(())
+        }).await
+    }
+
+    // Batches run one prepared statement per item on one connection, writes in
+    // one transaction so the rows are synced once
+
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let mut conn = pool::get(&pool)?;
+            let tx = conn.transaction().map_err(|e| ServerError::from(e).context("Create users error"))?;
+            let mut results = Vec::with_capacity(users.len());
+            {
+                let mut statement = tx.prepare_cached("INSERT INTO users (username, created_at, updated_at) VALUES (?1, ?2, ?2);")
+                    .map_err(|e| ServerError::from(e).context("Create users error"))?;
+                let now = now_millis();
+                for user in &users {
+                    match statement.execute(params![user.username, now]).map_err(ServerError::from) {
+                        Ok(_) => results.push(Ok(())),
+                        Err(ServerError::Conflict(_)) => {
+                            results.push(Err(ServerError::Conflict(format!("User already exists: {}", user.username))));
+                        }
+                        Err(e) => return Err(e.context("Create users error")),
+                    }
+                }
+            }
+            tx.commit().map_err(|e| ServerError::from(e).context("Create users error"))?;
+            Ok(results)
+        }).await
+    }
+
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let conn = pool::get(&pool)?;
+            let mut statement = conn.prepare_cached(&format!("SELECT {} FROM users WHERE username = ?;", USER_COLUMNS))
+                .map_err(|e| ServerError::from(e).context("Get users error"))?;
+            let mut results = Vec::with_capacity(usernames.len());
+            for username in usernames {
+                let user = statement.query_row(params![username], user_from_row);
+                match user.optional() {
+                    Ok(Some(user)) => results.push(Ok(user)),
+                    Ok(None) => results.push(Err(ServerError::NotFound(format!("User not found: {}", username)))),
+                    Err(e) => return Err(ServerError::from(e).context("Get users error")),
+                }
+            }
+            Ok(results)
+        }).await
+    }
+
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let mut conn = pool::get(&pool)?;
+            let tx = conn.transaction().map_err(|e| ServerError::from(e).context("Update users error"))?;
+            let mut results = Vec::with_capacity(updates.len());
+            {
+                let mut statement = tx.prepare_cached(&format!("UPDATE users SET {} WHERE username = ?9;", UPDATE_SET))
+                    .map_err(|e| ServerError::from(e).context("Update users error"))?;
+                for BatchUpdate { username, update } in &updates {
+                    let mut params = update_params(update);
+                    params.push(Box::new(username.clone()));
+                    match statement.execute(params_from_iter(&params)) {
+                        Ok(0) => results.push(Err(ServerError::NotFound(format!("User not found: {}", username)))),
+                        Ok(_) => results.push(Ok(())),
+                        Err(e) => return Err(ServerError::from(e).context("Update users error")),
+                    }
+                }
+            }
+            tx.commit().map_err(|e| ServerError::from(e).context("Update users error"))?;
+            Ok(results)
+        }).await
+    }
+
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error> {
+        let pool = self.pool.clone();
+        self.executor.run(move || {
+            let mut conn = pool::get(&pool)?;
+            let tx = conn.transaction().map_err(|e| ServerError::from(e).context("Delete users error"))?;
+            let mut results = Vec::with_capacity(usernames.len());
+            {
+                let mut statement = tx.prepare_cached("DELETE FROM users WHERE username = ?;")
+                    .map_err(|e| ServerError::from(e).context("Delete users error"))?;
+                for username in &usernames {
+                    match statement.execute(params![username]) {
+                        Ok(0) => results.push(Err(ServerError::NotFound(format!("User not found: {}", username)))),
+                        Ok(_) => results.push(Ok(())),
+                        Err(e) => return Err(ServerError::from(e).context("Delete users error")),
+                    }
+                }
+            }
+            tx.commit().map_err(|e| ServerError::from(e).context("Delete users error"))?;
+            Ok(results)
+        }).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        let state = self.pool.state();
+        let in_use = state.connections - state.idle_connections;
+        Some(self.waits.status(self.pool.max_size().into(), in_use.into(), state.idle_connections.into()))
+    }
+}
+
+/// Reads a user from the columns of `USER_COLUMNS`.
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    let attributes: String = row.get(5)?;
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        age: row.get(2)?,
+        email: row.get(3)?,
+        display_name: row.get(4)?,
+        attributes: serde_json::from_str(&attributes)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(5, rusqlite::types::Type::Text, Box::new(e)))?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+        version: row.get(8)?,
+    })
+}
+
+/// The values of `?1` to `?8` in `UPDATE_SET`, to which a statement adds its own.
+fn update_params(update: &UpdateUser) -> Vec<Box<dyn ToSql>> {
+    let (clear_attributes, attributes) = update.attributes_patch();
+    vec![
+        Box::new(update.age),
+        Box::new(update.email.is_some()),
+        Box::new(update.email.clone().flatten()),
+        Box::new(update.display_name.is_some()),
+        Box::new(update.display_name.clone().flatten()),
+        Box::new(clear_attributes),
+        Box::new(serde_json::Value::Object(attributes).to_string()),
+        Box::new(now_millis()),
+    ]
+}
+
+/// Why a write on `username` matched no row: the user is missing, or was found
+/// at another version than `expected_version`.
+fn unmatched(conn: &rusqlite::Connection, username: &str, expected_version: Option<u64>) -> ServerError {
+    let not_found = ServerError::NotFound(format!("User not found: {}", username));
+    if expected_version.is_none() {
+        return not_found;
+    }
+    let exists = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM users WHERE username = ?);",
+        params![username],
+        |row| row.get(0),
+    );
+    match exists {
+        Ok(true) => ServerError::PreconditionFailed(format!("User version mismatch: {}", username)),
+        Ok(false) => not_found,
+        Err(e) => ServerError::from(e).context("Check user version error"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::databases::conformance::conformance_tests!(SqliteDatabase);
+
+    #[tokio::test]
+    async fn test_init_adds_missing_columns() {
+        let path = std::env::temp_dir().join(format!("sqlite_migration_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // The table as the first version created it
+        rusqlite::Connection::open(&path).unwrap().execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, username TEXT NOT NULL UNIQUE, age INTEGER DEFAULT 0);
+            INSERT INTO users (username, age) VALUES ('legacy', 30);",
+        ).unwrap();
+
+        let mut config = Config::for_tests();
+        config.sqlite.path = path.to_string_lossy().into_owned();
+        for _ in 0..2 {
+            let db = SqliteDatabase::init(&config).await.unwrap();
+            let user = db.get_user("legacy".to_string()).await.unwrap();
+            assert_eq!((user.age, user.version, user.created_at, user.email), (30, 1, 0, None));
+            assert!(user.attributes.is_empty());
         }
+        let db = SqliteDatabase::init(&config).await.unwrap();
+        assert_eq!(db.update_user("legacy".to_string(), UpdateUser::with_age(31), Some(1)).await, Ok(2));
+        std::fs::remove_file(&path).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -5,80 +5,247 @@ use axum::{
 };
 use std::fmt;
 
-#[derive(Debug)]
-pub struct ServerError {
-    pub message: String,
+/// Error returned by every `Database` backend and by the HTTP handlers.
+///
+/// The variant decides the HTTP status code, the message is sent as the body.
+#[derive(Debug, PartialEq)]
+pub enum ServerError {
+    /// The requested user does not exist (404).
+    NotFound(String),
+    /// The operation clashes with existing data, e.g. a duplicate username (409).
+    Conflict(String),
+    /// The user is not at the version the request expected, see `If-Match` (412).
+    PreconditionFailed(String),
+    /// The input was rejected by the store, e.g. a value too long for its column (422).
+    Validation(String),
+    /// The store could not be reached or refused the connection (503).
+    Unavailable(String),
+    /// The store did not answer in time, e.g. pool checkout or lock timeout (504).
+    Timeout(String),
+    /// Anything else (500).
+    Internal(String),
 }
 
-impl fmt::Display for ServerError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+impl ServerError {
+    pub fn message(&self) -> &str {
+        match self {
+            ServerError::NotFound(message)
+            | ServerError::Conflict(message)
+            | ServerError::PreconditionFailed(message)
+            | ServerError::Validation(message)
+            | ServerError::Unavailable(message)
+            | ServerError::Timeout(message)
+            | ServerError::Internal(message) => message,
+        }
     }
-}
 
-impl std::error::Error for ServerError {}
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ServerError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServerError::Conflict(_) => StatusCode::CONFLICT,
+            ServerError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ServerError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServerError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ServerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 
-impl PartialEq for ServerError {
-    fn eq(&self, other: &Self) -> bool {
-        self.message == other.message
+    /// The variant in snake case, used as the error label of metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerError::NotFound(_) => "not_found",
+            ServerError::Conflict(_) => "conflict",
+            ServerError::PreconditionFailed(_) => "precondition_failed",
+            ServerError::Validation(_) => "validation",
+            ServerError::Unavailable(_) => "unavailable",
+            ServerError::Timeout(_) => "timeout",
+            ServerError::Internal(_) => "internal",
+        }
     }
-}
 
-impl ServerError {
-    pub fn new(message: &str) -> Self {
-        ServerError {
-            message: message.to_string(),
+    /// Prefixes the message with `context` while keeping the error kind.
+    pub fn context(self, context: &str) -> Self {
+        let wrap = |message: String| format!("{}: {}", context, message);
+        match self {
+            ServerError::NotFound(message) => ServerError::NotFound(wrap(message)),
+            ServerError::Conflict(message) => ServerError::Conflict(wrap(message)),
+            ServerError::PreconditionFailed(message) => ServerError::PreconditionFailed(wrap(message)),
+            ServerError::Validation(message) => ServerError::Validation(wrap(message)),
+            ServerError::Unavailable(message) => ServerError::Unavailable(wrap(message)),
+            ServerError::Timeout(message) => ServerError::Timeout(wrap(message)),
+            ServerError::Internal(message) => ServerError::Internal(wrap(message)),
         }
     }
 }
 
-impl From<String> for ServerError {
-    fn from(message: String) -> Self {
-        ServerError { message }
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
     }
 }
 
+impl std::error::Error for ServerError {}
+
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response<Body> {
-        // Log the error message
-        tracing::error!("Server error: {}", self.message);
-        let body = Body::from(self.message);
-        (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+        let status = self.status_code();
+        // Client errors are expected under load (e.g. `get.lua` on a missing user),
+        // so only server-side failures are logged at error level
+        if status.is_server_error() {
+            tracing::error!("Server error ({}): {}", status, self.message());
+        } else {
+            tracing::debug!("Client error ({}): {}", status, self.message());
+        }
+        let body = Body::from(self.to_string());
+        (status, body).into_response()
     }
 }
 
 impl From<r2d2::Error> for ServerError {
     fn from(err: r2d2::Error) -> Self {
-        ServerError::new(&format!("Database connection error: {}", err))
+        // r2d2 only fails a checkout once `connection_timeout` has elapsed
+        ServerError::Timeout(format!("Database connection error: {}", err))
     }
 }
 
 impl From<rusqlite::Error> for ServerError {
     fn from(err: rusqlite::Error) -> Self {
-        ServerError::new(&format!("SQLite error: {}", err))
+        use rusqlite::ErrorCode;
+
+        let message = format!("SQLite error: {}", err);
+        if let rusqlite::Error::QueryReturnedNoRows = err {
+            return ServerError::NotFound(message);
+        }
+        match err.sqlite_error_code() {
+            Some(ErrorCode::ConstraintViolation) => ServerError::Conflict(message),
+            Some(ErrorCode::TooBig) => ServerError::Validation(message),
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => ServerError::Timeout(message),
+            Some(ErrorCode::CannotOpen) => ServerError::Unavailable(message),
+            _ => ServerError::Internal(message),
+        }
     }
 }
 
 impl From<postgres::Error> for ServerError {
     fn from(err: postgres::Error) -> Self {
-        ServerError::new(&format!("PostgreSQL error: {}", err))
+        use postgres::error::SqlState;
+
+        let message = format!("PostgreSQL error: {}", err);
+        match err.code() {
+            Some(code) if *code == SqlState::UNIQUE_VIOLATION => ServerError::Conflict(message),
+            // Class 22: data exceptions (value too long, numeric out of range, ...)
+            Some(code) if code.code().starts_with("22") => ServerError::Validation(message),
+            Some(code) if *code == SqlState::QUERY_CANCELED || *code == SqlState::LOCK_NOT_AVAILABLE => {
+                ServerError::Timeout(message)
+            }
+            // Class 08: connection exceptions, 53: insufficient resources, 57P: operator intervention
+            Some(code)
+                if code.code().starts_with("08") || code.code().starts_with("53") || code.code().starts_with("57P") =>
+            {
+                ServerError::Unavailable(message)
+            }
+            Some(_) => ServerError::Internal(message),
+            None if err.is_closed() => ServerError::Unavailable(message),
+            None => {
+                let io_error = std::error::Error::source(&err).is_some_and(|source| source.is::<std::io::Error>());
+                if io_error {
+                    ServerError::Unavailable(message)
+                } else {
+                    ServerError::Internal(message)
+                }
+            }
+        }
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for ServerError {
+    fn from(err: deadpool_postgres::PoolError) -> Self {
+        use deadpool_postgres::PoolError;
+
+        match err {
+            PoolError::Backend(err) => ServerError::from(err).context("Database connection error"),
+            PoolError::Timeout(_) => ServerError::Timeout(format!("Database connection error: {}", err)),
+            PoolError::Closed => ServerError::Unavailable(format!("Database connection error: {}", err)),
+            _ => ServerError::Internal(format!("Database connection error: {}", err)),
+        }
+    }
+}
+
+impl From<deadpool_redis::PoolError> for ServerError {
+    fn from(err: deadpool_redis::PoolError) -> Self {
+        use deadpool_redis::PoolError;
+
+        match err {
+            PoolError::Backend(err) => ServerError::from(err).context("Database connection error"),
+            PoolError::Timeout(_) => ServerError::Timeout(format!("Database connection error: {}", err)),
+            PoolError::Closed => ServerError::Unavailable(format!("Database connection error: {}", err)),
+            _ => ServerError::Internal(format!("Database connection error: {}", err)),
+        }
     }
 }
 
 impl From<mysql_async::Error> for ServerError {
     fn from(err: mysql_async::Error) -> Self {
-        ServerError::new(&format!("MySQL error: {}", err))
+        let message = format!("MySQL error: {}", err);
+        match &err {
+            mysql_async::Error::Server(server_error) => match server_error.code {
+                // ER_DUP_ENTRY
+                1062 => ServerError::Conflict(message),
+                // ER_DATA_TOO_LONG, ER_WARN_DATA_OUT_OF_RANGE, ER_TRUNCATED_WRONG_VALUE_FOR_FIELD
+                1406 | 1264 | 1366 => ServerError::Validation(message),
+                // ER_LOCK_WAIT_TIMEOUT, ER_QUERY_TIMEOUT
+                1205 | 3024 => ServerError::Timeout(message),
+                // ER_CON_COUNT_ERROR, ER_SERVER_SHUTDOWN
+                1040 | 1053 => ServerError::Unavailable(message),
+                _ => ServerError::Internal(message),
+            },
+            mysql_async::Error::Io(_) | mysql_async::Error::Driver(_) => ServerError::Unavailable(message),
+            mysql_async::Error::Other(_) | mysql_async::Error::Url(_) => ServerError::Internal(message),
+        }
     }
 }
 
 impl From<redis::RedisError> for ServerError {
     fn from(err: redis::RedisError) -> Self {
-        ServerError::new(&format!("Redis error: {}", err))
+        let message = format!("Redis error: {}", err);
+        if err.is_timeout() {
+            ServerError::Timeout(message)
+        } else if err.is_connection_refusal() || err.is_connection_dropped() || err.is_io_error() {
+            ServerError::Unavailable(message)
+        } else {
+            ServerError::Internal(message)
+        }
     }
 }
 
 impl From<mongodb::error::Error> for ServerError {
     fn from(err: mongodb::error::Error) -> Self {
-        ServerError::new(&format!("MongoDB error: {}", err))
+        use mongodb::error::{ErrorKind, WriteFailure};
+
+        let message = format!("MongoDB error: {}", err);
+        match err.kind.as_ref() {
+            // E11000 duplicate key
+            ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000 => {
+                ServerError::Conflict(message)
+            }
+            // insert_many reports a write error per failed document
+            ErrorKind::InsertMany(insert_many_error)
+                if insert_many_error.write_errors.iter().flatten().any(|write_error| write_error.code == 11000) =>
+            {
+                ServerError::Conflict(message)
+            }
+            // KeyTooLong
+            ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 17280 => {
+                ServerError::Validation(message)
+            }
+            // MaxTimeMSExpired
+            ErrorKind::Command(command_error) if command_error.code == 50 => ServerError::Timeout(message),
+            ErrorKind::ServerSelection { .. }
+            | ErrorKind::Io(_)
+            | ErrorKind::ConnectionPoolCleared { .. }
+            | ErrorKind::DnsResolve { .. } => ServerError::Unavailable(message),
+            _ => ServerError::Internal(message),
+        }
     }
 }
@@ -0,0 +1,12 @@
+pub mod bench;
+pub mod compare;
+pub mod config;
+pub mod database;
+pub mod databases;
+pub mod err;
+pub mod logging;
+pub mod metrics;
+pub mod results;
+pub mod seed;
+pub mod server;
+pub mod telemetry;
@@ -0,0 +1,199 @@
+//! Logs of the HTTP server: which requests get logged per `LogMode`, and the
+//! text or JSON layer writing the logs.
+
+use axum::http::{Request, Response};
+use std::time::Duration;
+use tower_http::LatencyUnit;
+use tower_http::classify::{ServerErrorsAsFailures, ServerErrorsFailureClass, SharedClassifier};
+use tower_http::trace::{
+    DefaultOnBodyChunk, DefaultOnEos, DefaultOnFailure, DefaultOnRequest, DefaultOnResponse, MakeSpan, OnFailure,
+    OnRequest, OnResponse, TraceLayer,
+};
+use tracing::{Level, Span, Subscriber, field::Empty, info_span};
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::{LogFormat, LogMode, LoggingConfig};
+
+/// `TraceLayer` logging the requests selected by a `LogMode`.
+pub type RequestTraceLayer = TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    RequestLogger,
+    RequestLogger,
+    RequestLogger,
+    DefaultOnBodyChunk,
+    DefaultOnEos,
+    RequestLogger,
+>;
+
+/// Every `TraceLayer` callback in one type, so the mode is picked at runtime.
+#[derive(Clone, Debug)]
+pub struct RequestLogger {
+    mode: LogMode,
+    sample_rate: f64,
+}
+
+impl RequestLogger {
+    pub fn new(config: &LoggingConfig) -> Self {
+        RequestLogger { mode: config.mode, sample_rate: config.sample_rate }
+    }
+
+    pub fn layer(config: &LoggingConfig) -> RequestTraceLayer {
+        let logger = RequestLogger::new(config);
+        TraceLayer::new_for_http()
+            .make_span_with(logger.clone())
+            .on_request(logger.clone())
+            .on_response(logger.clone())
+            .on_failure(logger)
+    }
+}
+
+/// The server span of a request, parent of the `Database` spans. The route and
+/// status are recorded by `metrics::track_http` once known.
+impl<B> MakeSpan<B> for RequestLogger {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let span = info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            headers = Empty,
+            otel.name = Empty,
+            otel.kind = "server",
+            otel.status_code = Empty,
+            http.route = Empty,
+            http.response.status_code = Empty,
+        );
+        if self.mode == LogMode::Full {
+            span.record("headers", tracing::field::debug(request.headers()));
+        }
+        span
+    }
+}
+
+impl<B> OnRequest<B> for RequestLogger {
+    fn on_request(&mut self, request: &Request<B>, span: &Span) {
+        if self.mode == LogMode::Full {
+            DefaultOnRequest::new().level(Level::INFO).on_request(request, span);
+        }
+    }
+}
+
+impl<B> OnResponse<B> for RequestLogger {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        let log = match self.mode {
+            LogMode::Full => true,
+            LogMode::Sampled => rand::random_bool(self.sample_rate),
+            LogMode::Off | LogMode::ErrorsOnly => false,
+        };
+        if log {
+            DefaultOnResponse::new()
+                .level(Level::INFO)
+                .latency_unit(LatencyUnit::Micros)
+                .on_response(response, latency, span);
+        }
+    }
+}
+
+impl OnFailure<ServerErrorsFailureClass> for RequestLogger {
+    fn on_failure(&mut self, failure: ServerErrorsFailureClass, latency: Duration, span: &Span) {
+        if self.mode != LogMode::Off {
+            DefaultOnFailure::new()
+                .level(Level::ERROR)
+                .latency_unit(LatencyUnit::Micros)
+                .on_failure(failure, latency, span);
+        }
+    }
+}
+
+/// The layer writing logs to `writer`, or none when logging is off. Text logs
+/// are colored with `ansi`, which suits terminals only.
+pub fn fmt_layer<S, W>(config: &LoggingConfig, writer: W, ansi: bool) -> Option<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    match config.mode {
+        LogMode::Off => None,
+        _ if config.format == LogFormat::Json => Some(layer.json().boxed()),
+        _ => Some(layer.boxed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::level_filters::LevelFilter;
+    use tracing_subscriber::prelude::*;
+
+    use crate::config::Config;
+    use crate::database::Database;
+    use crate::databases::InMemoryDatabase;
+    use crate::server::{self, AppState};
+
+    /// Serves two found and one missing route under `mode`, returning the log lines.
+    async fn logs(mode: LogMode, format: LogFormat, sample_rate: f64) -> Vec<String> {
+        let config = LoggingConfig { mode, format, sample_rate };
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let output = output.clone();
+            move || Writer(output.clone())
+        };
+        let layer = fmt_layer(&config, writer, false).map(|layer| layer.with_filter(LevelFilter::INFO));
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+        let db = InMemoryDatabase::init(&Config::for_tests()).await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = server::router(AppState { db }, &config);
+        let server = tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::new();
+        for path in ["/", "/", "/users/nobody"] {
+            client.get(format!("{}{}", url, path)).send().await.unwrap();
+        }
+        server.abort();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+
+    /// Collects the output of the subscriber of one test.
+    struct Writer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Writer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_modes() {
+        // Started and finished lines for each request, the first with its headers
+        let full = logs(LogMode::Full, LogFormat::Text, 0.0).await;
+        assert_eq!(full.len(), 6, "{:#?}", full);
+        assert!(full[0].contains("headers=") && full[0].contains("started processing request"), "{}", full[0]);
+        assert_eq!(logs(LogMode::Sampled, LogFormat::Text, 1.0).await.len(), 3);
+        assert_eq!(logs(LogMode::Sampled, LogFormat::Text, 0.0).await, Vec::<String>::new());
+        // A missing user is the client's error, not the server's
+        assert_eq!(logs(LogMode::ErrorsOnly, LogFormat::Text, 1.0).await, Vec::<String>::new());
+        assert_eq!(logs(LogMode::Off, LogFormat::Text, 1.0).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_json_logs() {
+        let lines = logs(LogMode::Sampled, LogFormat::Json, 1.0).await;
+        assert_eq!(lines.len(), 3);
+        for line in lines {
+            let log: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(log["fields"]["message"], "finished processing request");
+            assert_eq!(log["span"]["method"], "GET");
+        }
+    }
+}
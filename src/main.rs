@@ -1,427 +1,74 @@
-use axum::{
-    Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{delete, get, patch, post},
-};
-use tower_http::{
-    LatencyUnit,
-    trace::{DefaultMakeSpan, DefaultOnFailure, DefaultOnRequest, DefaultOnResponse, TraceLayer},
-};
-use tracing::Level;
+use anyhow::Context;
 
-mod config;
-mod database;
-mod databases;
-mod err;
-
-use config::DatabaseType;
-use database::{CreateUser, Database, UpdateUser, User};
-use databases::*;
-use err::ServerError;
-
-#[derive(Clone)]
-pub struct AppState<T: Database> {
-    db: T,
-}
+use diesel_sqlite_benchmark::compare;
+use diesel_sqlite_benchmark::config::{Command, Config, DatabaseType};
+use diesel_sqlite_benchmark::database::Database;
+use diesel_sqlite_benchmark::databases::*;
+use diesel_sqlite_benchmark::seed;
+use diesel_sqlite_benchmark::server::{self, AppState};
+use diesel_sqlite_benchmark::telemetry;
 
 #[tokio::main]
-async fn main() {
-    // initialize tracing
-    tracing_subscriber::fmt::init();
-    
-    let db_type = DatabaseType::from_env();
-    println!("Using database type: {:?}", db_type);
-    
-    // Build our application with a route - need to match on db type
-    match db_type {
-        DatabaseType::Sqlite => {
-            let state = AppState { db: SqliteDatabase::init().await.expect("Failed to initialize SQLite") };
-            run_server(state).await;
-        },
-        DatabaseType::Postgres => {
-            let state = AppState { db: PostgresDatabase::init().await.expect("Failed to initialize PostgreSQL") };
-            run_server(state).await;
-        },
-        DatabaseType::MySql => {
-            let state = AppState { db: MySqlDatabase::init().await.expect("Failed to initialize MySQL") };
-            run_server(state).await;
-        },
-        DatabaseType::Redis => {
-            let state = AppState { db: RedisDatabase::init().await.expect("Failed to initialize Redis") };
-            run_server(state).await;
-        },
-        DatabaseType::MongoDB => {
-            let state = AppState { db: MongoDatabase::init().await.expect("Failed to initialize MongoDB") };
-            run_server(state).await;
-        },
+async fn main() -> anyhow::Result<()> {
+    let (config, command) = Config::load()?;
+    if let Some(Command::Compare(args)) = command {
+        // Not logging to stdout, since the compared backends serve from this process
+        let comparison = compare::compare(&config, &args).await?;
+        comparison.write(&args.output_dir)?;
+        println!("{}", comparison.to_markdown());
+        eprintln!("Results written to {}", args.output_dir.display());
+        return Ok(());
     }
-}
 
-async fn run_server<T: Database + 'static>(state: AppState<T>) {
-    let app = Router::new()
-        // `GET /` goes to `root`
-        .route("/", get(root::<T>))
-        // `GET /users/{username}` goes to `get_user_by_username`
-        .route("/users/{username}", get(get_user_by_username::<T>))
-        // `POST /users` goes to `create_user`
-        .route("/users", post(create_user::<T>))
-        // `PATCH /users/{username}` goes to `update_user_by_username`
-        .route("/users/{username}", patch(update_user_by_username::<T>))
-        // `DELETE /users/{username}` goes to `delete_user_by_username`
-        .route("/users/{username}", delete(delete_user_by_username::<T>))
-        .with_state(state)
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::new().include_headers(true))
-                .on_request(DefaultOnRequest::new().level(Level::INFO))
-                .on_response(
-                    DefaultOnResponse::new()
-                        .level(Level::INFO)
-                        .latency_unit(LatencyUnit::Micros),
-                )
-                .on_failure(
-                    DefaultOnFailure::new()
-                        .level(Level::ERROR)
-                        .latency_unit(LatencyUnit::Micros),
-                ),
-        );
-
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
-
-// basic handler that responds with a static string
-async fn root<T: Database>(State(_): State<AppState<T>>) -> &'static str {
-    "Hello, World!"
-}
-async fn create_user<T: Database>(
-    State(state): State<AppState<T>>,
-    Json(payload): Json<CreateUser>,
-) -> Result<String, ServerError> {
-    state.db.create_user(payload).await.map_err(|e| ServerError::new(&e.to_string()))
-}
-
-pub async fn get_user_by_username<T: Database>(
-    State(state): State<AppState<T>>,
-    Path(username): Path<String>,
-) -> Result<Json<User>, ServerError> {
-    let user = state.db.get_user(username).await.map_err(|e| ServerError::new(&e.to_string()))?;
-    Ok(Json(user))
-}
+    // Logs to stdout, and exports traces when an OTLP endpoint is configured
+    let telemetry = telemetry::init(&config)?;
 
-async fn update_user_by_username<T: Database>(
-    State(state): State<AppState<T>>,
-    Path(username): Path<String>,
-    Json(payload): Json<UpdateUser>,
-) -> Result<StatusCode, ServerError> {
-    state.db.update_user(username, payload).await.map_err(|e| ServerError::new(&e.to_string()))?;
-    Ok(StatusCode::OK)
-}
-
-pub async fn delete_user_by_username<T: Database>(
-    State(state): State<AppState<T>>,
-    Path(username): Path<String>,
-) -> Result<StatusCode, ServerError> {
-    state.db.delete_user(username).await.map_err(|e| ServerError::new(&e.to_string()))?;
-    Ok(StatusCode::OK)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    async fn create_test_state() -> AppState<SqliteDatabase> {
-        AppState { db: SqliteDatabase::init().await.unwrap() }
-    }
-
-    #[tokio::test]
-    async fn test_root() {
-        let state = create_test_state().await;
-        let response = root(State(state)).await;
-        assert_eq!(response, "Hello, World!");
-    }
-    
-    #[tokio::test]
-    async fn test_create_user() {
-        let payload = CreateUser {
-            username: "testuser".to_string(),
-        };
-        let state = create_test_state().await;
-        let status = create_user(State(state), Json(payload)).await;
-        assert_eq!(
-            status,
-            Ok("User created with username: testuser".to_string())
-        );
-    }
-    
-    #[tokio::test]
-    async fn test_get_user_by_username() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "testuser".to_string(),
-        };
-        create_user(State(state.clone()), Json(payload))
-            .await
-            .unwrap();
-
-        let response = get_user_by_username(State(state), Path("testuser".to_string())).await;
-        assert!(response.is_ok());
-        let user = response.unwrap().0;
-        assert_eq!(user.username, "testuser");
+    let db_type = config.server.database;
+    println!("Using database type: {:?}", db_type);
+    if matches!(db_type, DatabaseType::Sqlite | DatabaseType::Postgres) {
+        println!("Using database executor: {:?}", config.executor.mode);
     }
-
-    #[tokio::test]
-    async fn test_update_user_by_username() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "testuser".to_string(),
-        };
-        create_user(State(state.clone()), Json(payload))
-            .await
-            .unwrap();
-
-        let update_payload = UpdateUser { age: 30 };
-        let response = update_user_by_username(
-            State(state.clone()),
-            Path("testuser".to_string()),
-            Json(update_payload),
-        )
-        .await;
-
-        assert!(response.is_ok());
-        assert_eq!(response.unwrap(), StatusCode::OK);
-
-        // Verify the update
-        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
-        let user = user_response.unwrap().0;
-        assert_eq!(user.username, "testuser");
-        assert_eq!(user.age, 30);
+    if matches!(db_type, DatabaseType::Redis) {
+        println!("Using Redis connection mode: {:?}", config.redis.mode);
     }
     
-    #[tokio::test]
-    async fn test_delete_user_by_username() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "testuser".to_string(),
-        };
-        create_user(State(state.clone()), Json(payload))
-            .await
-            .unwrap();
-
-        let response =
-            delete_user_by_username(State(state.clone()), Path("testuser".to_string())).await;
-        assert!(response.is_ok());
-        assert_eq!(response.unwrap(), StatusCode::OK);
-
-        // Verify the deletion
-        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
-        assert!(user_response.is_err());
-    }
-
-    // FAILURE SCENARIO TESTS
-    #[tokio::test]
-    async fn test_get_nonexistent_user() {
-        let state = create_test_state().await;
-        
-        let response = get_user_by_username(State(state), Path("nonexistent".to_string())).await;
-        assert!(response.is_err());
-        
-        let error = response.unwrap_err();
-        assert!(error.message.contains("Get user by username error"));
-    }
-
-    #[tokio::test] 
-    async fn test_update_nonexistent_user() {
-        let state = create_test_state().await;
-        let update_payload = UpdateUser { age: 25 };
-        
-        let response = update_user_by_username(
-            State(state),
-            Path("nonexistent".to_string()),
-            Json(update_payload),
-        ).await;
-        
-        // Update operation should succeed even if no rows are affected
-        // This is the current behavior - update returns OK even for non-existent users
-        assert!(response.is_ok());
-        assert_eq!(response.unwrap(), StatusCode::OK);
-    }
-
-    #[tokio::test]
-    async fn test_delete_nonexistent_user() {
-        let state = create_test_state().await;
-        
-        let response = delete_user_by_username(State(state), Path("nonexistent".to_string())).await;
-        
-        // Delete operation should succeed even if no rows are affected
-        // This is the current behavior - delete returns OK even for non-existent users  
-        assert!(response.is_ok());
-        assert_eq!(response.unwrap(), StatusCode::OK);
-    }
-
-    #[tokio::test]
-    async fn test_create_duplicate_user() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "duplicate_user".to_string(),
-        };
-        
-        // Create the user first time - should succeed
-        let first_response = create_user(State(state.clone()), Json(payload.clone())).await;
-        assert!(first_response.is_ok());
-        
-        // Try to create the same user again - should fail due to UNIQUE constraint
-        let second_response = create_user(State(state), Json(payload)).await;
-        assert!(second_response.is_err());
-        
-        let error = second_response.unwrap_err();
-        assert!(error.message.contains("Create user") && error.message.contains("error"));
-    }
-
-    #[tokio::test]
-    async fn test_create_user_empty_username() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "".to_string(),
-        };
-        
-        let response = create_user(State(state), Json(payload)).await;
-        // Empty username should be allowed by current implementation
-        // The database will accept empty strings as valid usernames
-        assert!(response.is_ok());
-    }
-
-    #[tokio::test]
-    async fn test_update_user_with_large_age() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "testuser".to_string(),
-        };
-        create_user(State(state.clone()), Json(payload))
-            .await
-            .unwrap();
-
-        // Test with maximum u32 value
-        let update_payload = UpdateUser { age: u32::MAX };
-        let response = update_user_by_username(
-            State(state.clone()),
-            Path("testuser".to_string()),
-            Json(update_payload),
-        ).await;
-        
-        assert!(response.is_ok());
-        assert_eq!(response.unwrap(), StatusCode::OK);
-        
-        // Verify the update worked
-        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
-        assert!(user_response.is_ok());
-        let user = user_response.unwrap().0;
-        assert_eq!(user.age, u32::MAX);
-    }
-
-    #[tokio::test]
-    async fn test_create_user_with_very_long_username() {
-        let state = create_test_state().await;
-        // Create a very long username (1000 characters)
-        let long_username = "a".repeat(1000);
-        let payload = CreateUser {
-            username: long_username.clone(),
-        };
-        
-        let response = create_user(State(state.clone()), Json(payload)).await;
-        assert!(response.is_ok());
-        
-        // Verify we can retrieve the user with long username
-        let user_response = get_user_by_username(State(state), Path(long_username)).await;
-        assert!(user_response.is_ok());
-    }
+    // Connect to the selected backend - need to match on db type
+    let result = match db_type {
+        DatabaseType::Sqlite => run::<SqliteDatabase>(&config, command, "SQLite").await,
+        DatabaseType::InMemory => run::<InMemoryDatabase>(&config, command, "in-memory database").await,
+        DatabaseType::Postgres => run::<PostgresDatabase>(&config, command, "PostgreSQL").await,
+        DatabaseType::PostgresAsync => run::<AsyncPostgresDatabase>(&config, command, "async PostgreSQL").await,
+        DatabaseType::MySql => run::<MySqlDatabase>(&config, command, "MySQL").await,
+        DatabaseType::Redis => run::<RedisDatabase>(&config, command, "Redis").await,
+        DatabaseType::MongoDB => run::<MongoDatabase>(&config, command, "MongoDB").await,
+    };
+    telemetry.shutdown();
+    result
+}
 
-    #[tokio::test]
-    async fn test_create_user_with_special_characters() {
-        let state = create_test_state().await;
-        let special_username = "user@#$%^&*()_+-=[]{}|;:,.<>?".to_string();
-        let payload = CreateUser {
-            username: special_username.clone(),
-        };
-        
-        let response = create_user(State(state.clone()), Json(payload)).await;
-        assert!(response.is_ok());
-        
-        // Verify we can retrieve the user with special characters
-        let user_response = get_user_by_username(State(state), Path(special_username)).await;
-        assert!(user_response.is_ok());
+/// Serves HTTP, or runs `command` against the backend when one is given.
+async fn run<T: Database + 'static>(config: &Config, command: Option<Command>, name: &str) -> anyhow::Result<()> {
+    let db = T::init(config).await.with_context(|| format!("Failed to initialize {}", name))?;
+    match command {
+        None => run_server(AppState { db }, config).await,
+        Some(Command::Seed(args)) => {
+            println!("Seeding {} users from user{}", args.users, args.start);
+            let seeded = seed::seed(&db, &args).await?;
+            println!("Seeded {} users in {:.2}s", seeded.users, seeded.elapsed.as_secs_f64());
+            Ok(())
+        }
+        Some(Command::Compare(_)) => unreachable!("compare connects to each backend itself"),
     }
+}
 
-    #[tokio::test]
-    async fn test_update_user_zero_age() {
-        let state = create_test_state().await;
-        let payload = CreateUser {
-            username: "testuser".to_string(),
-        };
-        create_user(State(state.clone()), Json(payload))
-            .await
-            .unwrap();
-
-        // Test with zero age
-        let update_payload = UpdateUser { age: 0 };
-        let response = update_user_by_username(
-            State(state.clone()),
-            Path("testuser".to_string()),
-            Json(update_payload),
-        ).await;
-        
-        assert!(response.is_ok());
-        assert_eq!(response.unwrap(), StatusCode::OK);
-        
-        // Verify the update worked
-        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
-        assert!(user_response.is_ok());
-        let user = user_response.unwrap().0;
-        assert_eq!(user.age, 0);
-    }
+async fn run_server<T: Database + 'static>(state: AppState<T>, config: &Config) -> anyhow::Result<()> {
+    let app = server::router(state, &config.logging);
+    let listen = &config.server.listen;
 
-    #[tokio::test]
-    async fn test_multiple_operations_on_same_user() {
-        let state = create_test_state().await;
-        let username = "multiop_user".to_string();
-        
-        // Create user
-        let create_payload = CreateUser {
-            username: username.clone(),
-        };
-        let create_response = create_user(State(state.clone()), Json(create_payload)).await;
-        assert!(create_response.is_ok());
-        
-        // Get user
-        let get_response = get_user_by_username(State(state.clone()), Path(username.clone())).await;
-        assert!(get_response.is_ok());
-        let user = get_response.unwrap().0;
-        assert_eq!(user.username, username);
-        assert_eq!(user.age, 0); // Default age
-        
-        // Update user
-        let update_payload = UpdateUser { age: 42 };
-        let update_response = update_user_by_username(
-            State(state.clone()),
-            Path(username.clone()),
-            Json(update_payload),
-        ).await;
-        assert!(update_response.is_ok());
-        
-        // Get user again to verify update
-        let get_response2 = get_user_by_username(State(state.clone()), Path(username.clone())).await;
-        assert!(get_response2.is_ok());
-        let user2 = get_response2.unwrap().0;
-        assert_eq!(user2.age, 42);
-        
-        // Delete user
-        let delete_response = delete_user_by_username(State(state.clone()), Path(username.clone())).await;
-        assert!(delete_response.is_ok());
-        
-        // Try to get user after deletion - should fail
-        let get_response3 = get_user_by_username(State(state), Path(username)).await;
-        assert!(get_response3.is_err());
-    }
+    // run our app with hyper, on `server.listen` (0.0.0.0:3000 by default)
+    let listener = tokio::net::TcpListener::bind(listen).await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    println!("Listening on {}", listen);
+    axum::serve(listener, app).await?;
+    Ok(())
 }
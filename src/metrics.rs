@@ -0,0 +1,328 @@
+//! Prometheus metrics served on `/metrics`: requests per route, calls per
+//! `Database` method and connection pool state.
+//!
+//! The metrics live in one registry per process, labelled with the backend so
+//! the servers that `compare` runs side by side stay apart.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    CounterVec, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tracing::{Instrument, field::Empty, info_span};
+
+use crate::config::Config;
+use crate::database::{
+    BatchResult, BatchUpdate, CreateUser, Database, ListUsers, NewUser, PoolStatus, UpdateUser, Upserted, User,
+};
+use crate::err::ServerError;
+
+/// Histogram buckets in seconds, from the in-memory backend's tens of
+/// microseconds to connection timeouts.
+const LATENCY_BUCKETS: [f64; 16] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    db_operations: IntCounterVec,
+    db_duration: HistogramVec,
+    pool_max_connections: IntGaugeVec,
+    pool_connections: IntGaugeVec,
+    pool_checkouts: IntCounterVec,
+    pool_wait: CounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// The process-wide metrics.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            let opts = HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec());
+            let histogram = HistogramVec::new(opts, labels).expect("valid histogram");
+            registry.register(Box::new(histogram.clone())).expect("metric registered once");
+            histogram
+        };
+        let http_duration = histogram("http_request_duration_seconds", "Time to respond to HTTP requests", &["method", "route"]);
+        let db_duration = histogram("db_operation_duration_seconds", "Time taken by Database methods", &["backend", "operation"]);
+
+        macro_rules! register {
+            ($type:ty, $name:literal, $help:literal, $labels:expr) => {{
+                let metric = <$type>::new(Opts::new($name, $help), $labels).expect("valid metric");
+                registry.register(Box::new(metric.clone())).expect("metric registered once");
+                metric
+            }};
+        }
+        Metrics {
+            http_requests: register!(IntCounterVec, "http_requests_total", "HTTP requests answered", &["method", "route", "status"]),
+            http_duration,
+            db_operations: register!(
+                IntCounterVec,
+                "db_operations_total",
+                "Database method calls by result: ok or the error kind",
+                &["backend", "operation", "result"]
+            ),
+            db_duration,
+            pool_max_connections: register!(IntGaugeVec, "db_pool_max_connections", "Size limit of the connection pool", &["backend"]),
+            pool_connections: register!(IntGaugeVec, "db_pool_connections", "Open pool connections, in use or idle", &["backend", "state"]),
+            pool_checkouts: register!(
+                IntCounterVec,
+                "db_pool_checkouts_total",
+                "Connection checkouts from the pool, including failed ones",
+                &["backend"]
+            ),
+            pool_wait: register!(
+                CounterVec,
+                "db_pool_wait_seconds_total",
+                "Time spent waiting for pool connections",
+                &["backend"]
+            ),
+            registry,
+        }
+    }
+
+    pub fn observe_http(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.http_requests.with_label_values(&[method, route, &status.to_string()]).inc();
+        self.http_duration.with_label_values(&[method, route]).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_db(&self, backend: &str, operation: &str, error: Option<&ServerError>, elapsed: Duration) {
+        let result = error.map_or("ok", ServerError::kind);
+        self.db_operations.with_label_values(&[backend, operation, result]).inc();
+        self.db_duration.with_label_values(&[backend, operation]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Copies a pool status read at scrape time into the gauges and counters.
+    pub fn set_pool_status(&self, backend: &str, status: &PoolStatus) {
+        self.pool_max_connections.with_label_values(&[backend]).set(status.max_size as i64);
+        self.pool_connections.with_label_values(&[backend, "in_use"]).set(status.in_use as i64);
+        self.pool_connections.with_label_values(&[backend, "idle"]).set(status.idle as i64);
+        // Counters only go up, so a backend that was started again keeps its old total
+        let checkouts = self.pool_checkouts.with_label_values(&[backend]);
+        checkouts.inc_by(status.checkouts.saturating_sub(checkouts.get()));
+        let wait = self.pool_wait.with_label_values(&[backend]);
+        wait.inc_by((status.wait.as_secs_f64() - wait.get()).max(0.0));
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn render(&self) -> Result<String, ServerError> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| ServerError::Internal(format!("Failed to encode metrics: {}", e)))?;
+        String::from_utf8(buffer).map_err(|e| ServerError::Internal(format!("Failed to encode metrics: {}", e)))
+    }
+}
+
+/// Middleware counting and timing requests per route pattern, e.g.
+/// `/users/{username}` rather than every username, so it is added with
+/// `Router::route_layer` where the matched path is known. Also names the
+/// request span after the route.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let method = request.method().clone();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let route = route.as_deref().unwrap_or("unmatched");
+    let status = response.status().as_u16();
+    metrics().observe_http(method.as_str(), route, status, started.elapsed());
+    let span = tracing::Span::current();
+    span.record("otel.name", format!("{} {}", method, route));
+    span.record("http.route", route);
+    span.record("http.response.status_code", status);
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "error");
+    }
+    response
+}
+
+/// Decorates a backend with the `db_operation*` metrics of each method call,
+/// and with a client span per call for the trace export.
+#[derive(Clone)]
+pub struct Instrumented<T> {
+    inner: T,
+}
+
+impl<T: Database> Instrumented<T> {
+    pub fn new(inner: T) -> Self {
+        Instrumented { inner }
+    }
+
+    async fn observe<R>(
+        &self,
+        operation: &'static str,
+        call: impl Future<Output = Result<R, T::Error>>,
+    ) -> Result<R, ServerError> {
+        let span = info_span!(
+            "db",
+            otel.name = operation,
+            otel.kind = "client",
+            otel.status_code = Empty,
+            db.system = db_system(T::NAME),
+            db.operation = operation,
+            error.type = Empty,
+        );
+        let started = Instant::now();
+        let result = call.instrument(span.clone()).await.map_err(Into::into);
+        let error = result.as_ref().err();
+        metrics().observe_db(T::NAME, operation, error, started.elapsed());
+        if let Some(error) = error {
+            span.record("otel.status_code", "error");
+            span.record("error.type", error.kind());
+        }
+        result
+    }
+
+    /// `observe` for the batch methods, whose items fail with `T::Error` too.
+    async fn observe_batch<R>(
+        &self,
+        operation: &'static str,
+        call: impl Future<Output = BatchResult<R, T::Error>>,
+    ) -> BatchResult<R, ServerError> {
+        let results = self.observe(operation, call).await?;
+        Ok(results.into_iter().map(|result| result.map_err(Into::into)).collect())
+    }
+}
+
+/// The `db.system` of the semantic conventions for a backend name.
+fn db_system(backend: &'static str) -> &'static str {
+    match backend {
+        "postgres" | "postgres-async" => "postgresql",
+        backend => backend,
+    }
+}
+
+#[async_trait]
+impl<T: Database> Database for Instrumented<T> {
+    type Error = ServerError;
+    const NAME: &'static str = T::NAME;
+
+    async fn init(config: &Config) -> Result<Self, Self::Error> {
+        Ok(Instrumented::new(T::init(config).await.map_err(Into::into)?))
+    }
+
+    async fn create_user(&self, user: CreateUser) -> Result<String, Self::Error> {
+        self.observe("create_user", self.inner.create_user(user)).await
+    }
+
+    async fn get_user(&self, username: String) -> Result<User, Self::Error> {
+        self.observe("get_user", self.inner.get_user(username)).await
+    }
+
+    async fn update_user(
+        &self,
+        username: String,
+        update: UpdateUser,
+        expected_version: Option<u64>,
+    ) -> Result<u64, Self::Error> {
+        self.observe("update_user", self.inner.update_user(username, update, expected_version)).await
+    }
+
+    async fn delete_user(&self, username: String, expected_version: Option<u64>) -> Result<(), Self::Error> {
+        self.observe("delete_user", self.inner.delete_user(username, expected_version)).await
+    }
+
+    async fn upsert_user(&self, username: String, update: UpdateUser) -> Result<Upserted, Self::Error> {
+        self.observe("upsert_user", self.inner.upsert_user(username, update)).await
+    }
+
+    async fn insert_users(&self, users: Vec<NewUser>) -> Result<(), Self::Error> {
+        self.observe("insert_users", self.inner.insert_users(users)).await
+    }
+
+    async fn count_users(&self) -> Result<u64, Self::Error> {
+        self.observe("count_users", self.inner.count_users()).await
+    }
+
+    async fn list_users(&self, list: ListUsers) -> Result<Vec<User>, Self::Error> {
+        self.observe("list_users", self.inner.list_users(list)).await
+    }
+
+    async fn delete_all_users(&self) -> Result<(), Self::Error> {
+        self.observe("delete_all_users", self.inner.delete_all_users()).await
+    }
+
+    async fn create_users(&self, users: Vec<CreateUser>) -> BatchResult<(), Self::Error> {
+        self.observe_batch("create_users", self.inner.create_users(users)).await
+    }
+
+    async fn get_users(&self, usernames: Vec<String>) -> BatchResult<User, Self::Error> {
+        self.observe_batch("get_users", self.inner.get_users(usernames)).await
+    }
+
+    async fn update_users(&self, updates: Vec<BatchUpdate>) -> BatchResult<(), Self::Error> {
+        self.observe_batch("update_users", self.inner.update_users(updates)).await
+    }
+
+    async fn delete_users(&self, usernames: Vec<String>) -> BatchResult<(), Self::Error> {
+        self.observe_batch("delete_users", self.inner.delete_users(usernames)).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        self.inner.pool_status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::databases::{InMemoryDatabase, SqliteDatabase};
+
+    /// Value of the sample `name{labels}` in the rendered metrics.
+    fn sample(name_and_labels: &str) -> Option<f64> {
+        metrics()
+            .render()
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix(name_and_labels)?.trim().parse().ok())
+    }
+
+    #[tokio::test]
+    async fn test_instrumented_counts_results() {
+        let db = Instrumented::<InMemoryDatabase>::init(&Config::for_tests()).await.unwrap();
+        db.create_user(CreateUser { username: "metrics".to_string() }).await.unwrap();
+        assert!(db.get_user("missing".to_string()).await.is_err());
+        assert_eq!(db.get_user("metrics".to_string()).await.unwrap().username, "metrics");
+        assert_eq!(Instrumented::<InMemoryDatabase>::NAME, "memory");
+
+        // Other tests share the registry, so only check the calls made here happened
+        let ok = r#"db_operations_total{backend="memory",operation="create_user",result="ok"}"#;
+        assert!(sample(ok).unwrap() >= 1.0);
+        let not_found = r#"db_operations_total{backend="memory",operation="get_user",result="not_found"}"#;
+        assert!(sample(not_found).unwrap() >= 1.0);
+        let timed = r#"db_operation_duration_seconds_count{backend="memory",operation="get_user"}"#;
+        assert!(sample(timed).unwrap() >= 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_status_is_exported() {
+        let db = Instrumented::<SqliteDatabase>::init(&Config::for_tests()).await.unwrap();
+        db.count_users().await.unwrap();
+        let status = db.pool_status().unwrap();
+        assert_eq!((status.max_size, status.in_use, status.idle), (1, 0, 1));
+        assert!(status.checkouts >= 2);
+
+        metrics().set_pool_status("test-pool", &status);
+        assert_eq!(sample(r#"db_pool_max_connections{backend="test-pool"}"#), Some(1.0));
+        assert_eq!(sample(r#"db_pool_connections{backend="test-pool",state="idle"}"#), Some(1.0));
+        assert_eq!(sample(r#"db_pool_checkouts_total{backend="test-pool"}"#), Some(status.checkouts as f64));
+
+        // Snapshots from a restarted backend never lower the counters
+        metrics().set_pool_status("test-pool", &PoolStatus { checkouts: 1, ..status.clone() });
+        assert_eq!(sample(r#"db_pool_checkouts_total{backend="test-pool"}"#), Some(status.checkouts as f64));
+    }
+}
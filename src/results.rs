@@ -0,0 +1,596 @@
+//! Local store of benchmark runs, and regression checks between them.
+//!
+//! Each saved run is a JSON `RunRecord` under `<dir>/runs/`, named by its id.
+//! `<dir>/baselines.json` maps `backend/workload` to the id of the run later
+//! runs of that backend and workload are checked against.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bench::Report;
+use crate::config::Config;
+
+pub const DEFAULT_DIR: &str = "results";
+
+/// A saved benchmark run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub id: String,
+    /// Milliseconds since the Unix epoch when the run was saved.
+    pub timestamp_ms: u64,
+    /// `None` when not run from a git checkout.
+    pub git: Option<GitInfo>,
+    pub backend: String,
+    pub workload: String,
+    pub machine: MachineInfo,
+    /// Server settings, only known when the server ran in-process (`compare`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<Config>,
+    /// Load settings and metrics.
+    pub report: Report,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct GitInfo {
+    pub commit: String,
+    /// Whether tracked files had uncommitted changes.
+    pub dirty: bool,
+}
+
+impl GitInfo {
+    pub fn current() -> Option<Self> {
+        let git = |args: &[&str]| {
+            let output = Command::new("git").args(args).output().ok()?;
+            output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        Some(GitInfo {
+            commit: git(&["rev-parse", "HEAD"])?,
+            dirty: !git(&["status", "--porcelain", "--untracked-files=no"])?.is_empty(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MachineInfo {
+    pub hostname: Option<String>,
+    pub os: String,
+    pub arch: String,
+    /// Threads available to the process.
+    pub cpus: usize,
+    pub cpu_model: Option<String>,
+    pub memory_bytes: Option<u64>,
+}
+
+impl MachineInfo {
+    /// The model and memory are read from `/proc`, so only known on Linux.
+    pub fn current() -> Self {
+        let read = |path: &str| std::fs::read_to_string(path).ok();
+        let field = |contents: &str, name: &str| {
+            contents
+                .lines()
+                .find(|line| line.starts_with(name))
+                .and_then(|line| line.split_once(':'))
+                .map(|(_, value)| value.trim().to_string())
+        };
+        MachineInfo {
+            hostname: read("/proc/sys/kernel/hostname")
+                .map(|hostname| hostname.trim().to_string())
+                .or_else(|| std::env::var("HOSTNAME").ok()),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpus: std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+            cpu_model: read("/proc/cpuinfo").and_then(|cpuinfo| field(&cpuinfo, "model name")),
+            memory_bytes: read("/proc/meminfo")
+                .and_then(|meminfo| field(&meminfo, "MemTotal"))
+                .and_then(|total| total.trim_end_matches("kB").trim().parse::<u64>().ok())
+                .map(|kb| kb * 1024),
+        }
+    }
+}
+
+/// Saved runs and baselines in a directory.
+pub struct ResultsStore {
+    dir: PathBuf,
+}
+
+impl ResultsStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ResultsStore { dir: dir.into() }
+    }
+
+    fn runs_dir(&self) -> PathBuf {
+        self.dir.join("runs")
+    }
+
+    fn baselines_path(&self) -> PathBuf {
+        self.dir.join("baselines.json")
+    }
+
+    /// Records `report` with the current commit and machine, and returns its id.
+    pub fn save(&self, backend: &str, report: Report, config: Option<Config>) -> anyhow::Result<String> {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        // The backend is a free-form label from `bench --backend`
+        let backend: String = backend
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let record = RunRecord {
+            id: format!("{}-{}-{}", timestamp_ms, backend, report.workload),
+            timestamp_ms,
+            git: GitInfo::current(),
+            workload: report.workload.clone(),
+            backend,
+            machine: MachineInfo::current(),
+            config,
+            report,
+        };
+
+        let dir = self.runs_dir();
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.json", record.id));
+        std::fs::write(&path, serde_json::to_string_pretty(&record)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(record.id)
+    }
+
+    /// Loads a run by id, by the path of its file, or the newest run for `latest`.
+    pub fn load(&self, run: &str) -> anyhow::Result<RunRecord> {
+        if run == "latest" {
+            return self.list()?.pop().context("No saved runs");
+        }
+        let path = match Path::new(run) {
+            path if path.is_file() => path.to_path_buf(),
+            _ => self.runs_dir().join(format!("{}.json", run)),
+        };
+        let contents = std::fs::read_to_string(&path).with_context(|| format!("Failed to read run {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid run {}", path.display()))
+    }
+
+    /// Every saved run, oldest first.
+    pub fn list(&self) -> anyhow::Result<Vec<RunRecord>> {
+        let dir = self.runs_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut runs = Vec::new();
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                runs.push(self.load(&path.to_string_lossy())?);
+            }
+        }
+        runs.sort_by(|a, b| (a.timestamp_ms, &a.id).cmp(&(b.timestamp_ms, &b.id)));
+        Ok(runs)
+    }
+
+    fn baselines(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        let path = self.baselines_path();
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid baselines file {}", path.display()))
+    }
+
+    /// Makes `run` the baseline of its backend and workload, returning its record.
+    pub fn set_baseline(&self, run: &str) -> anyhow::Result<RunRecord> {
+        let record = self.load(run)?;
+        let mut baselines = self.baselines()?;
+        baselines.insert(baseline_key(&record.backend, &record.workload), record.id.clone());
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.baselines_path();
+        std::fs::write(&path, serde_json::to_string_pretty(&baselines)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(record)
+    }
+
+    pub fn baseline(&self, backend: &str, workload: &str) -> anyhow::Result<Option<RunRecord>> {
+        match self.baselines()?.get(&baseline_key(backend, workload)) {
+            Some(id) => self.load(id).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn baseline_key(backend: &str, workload: &str) -> String {
+    format!("{}/{}", backend, workload)
+}
+
+/// When a change between two runs counts as a regression or an improvement.
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    /// Largest p-value still counted as significant.
+    pub alpha: f64,
+    /// Smallest relative change that matters, e.g. 0.05 for 5%.
+    pub min_change: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds { alpha: 0.05, min_change: 0.05 }
+    }
+}
+
+/// Error rates only regress when they rise by more than this many percentage points.
+const ERROR_RATE_TOLERANCE: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+/// One metric of two runs side by side.
+#[derive(Debug, Serialize)]
+pub struct MetricComparison {
+    pub metric: &'static str,
+    pub unit: &'static str,
+    pub baseline: f64,
+    pub candidate: f64,
+    /// Relative change from the baseline, positive when the value grew.
+    pub change: f64,
+    /// Welch's t-test over the per-second samples, `None` for metrics without
+    /// samples, which are judged on `Thresholds::min_change` alone.
+    pub p_value: Option<f64>,
+    pub verdict: Verdict,
+}
+
+impl MetricComparison {
+    fn new(
+        metric: &'static str,
+        unit: &'static str,
+        higher_is_better: bool,
+        (baseline, candidate): (f64, f64),
+        p_value: Option<f64>,
+        thresholds: Thresholds,
+    ) -> Self {
+        let change = if baseline == 0.0 {
+            if candidate == 0.0 { 0.0 } else { f64::INFINITY.copysign(candidate) }
+        } else {
+            (candidate - baseline) / baseline
+        };
+        let worse = if higher_is_better { -change } else { change };
+        let verdict = if worse.abs() < thresholds.min_change || p_value.is_some_and(|p| p > thresholds.alpha) {
+            Verdict::Unchanged
+        } else if worse > 0.0 {
+            Verdict::Regression
+        } else {
+            Verdict::Improvement
+        };
+        MetricComparison { metric, unit, baseline, candidate, change, p_value, verdict }
+    }
+}
+
+fn error_rate(report: &Report) -> f64 {
+    let total = &report.total;
+    let sent = total.requests + total.transport_errors;
+    if sent == 0 {
+        return 0.0;
+    }
+    (total.requests - total.successes + total.transport_errors) as f64 / sent as f64 * 100.0
+}
+
+fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Compares the metrics of two reports. Throughput and mean latency are tested
+/// for significance over the per-second timeline; percentiles are judged on the
+/// size of the change alone since a run only has one of each.
+pub fn compare_reports(baseline: &Report, candidate: &Report, thresholds: Thresholds) -> Vec<MetricComparison> {
+    let throughput = |report: &Report| report.timeline.iter().map(|second| second.requests as f64).collect::<Vec<_>>();
+    // Seconds without responses have no latency to average
+    let latency = |report: &Report| {
+        report.timeline.iter().filter(|second| second.requests > 0).map(|second| second.mean_latency_us / 1000.0).collect::<Vec<_>>()
+    };
+    let sampled = |baseline: Vec<f64>, candidate: Vec<f64>, fallback: (f64, f64)| match welch_t_test(&baseline, &candidate) {
+        Some(p_value) => ((mean(&baseline), mean(&candidate)), Some(p_value)),
+        None => (fallback, None),
+    };
+    let ms = |us: u64| us as f64 / 1000.0;
+    let (base, cand) = (&baseline.total, &candidate.total);
+
+    let (values, p_value) = sampled(throughput(baseline), throughput(candidate), (base.requests_per_sec, cand.requests_per_sec));
+    let mut metrics = vec![MetricComparison::new("throughput", "req/s", true, values, p_value, thresholds)];
+    let (values, p_value) = sampled(latency(baseline), latency(candidate), (base.latency.mean_us / 1000.0, cand.latency.mean_us / 1000.0));
+    metrics.push(MetricComparison::new("mean latency", "ms", false, values, p_value, thresholds));
+    for (metric, base, cand) in [
+        ("p50 latency", base.latency.p50_us, cand.latency.p50_us),
+        ("p99 latency", base.latency.p99_us, cand.latency.p99_us),
+        ("p99.9 latency", base.latency.p99_9_us, cand.latency.p99_9_us),
+    ] {
+        metrics.push(MetricComparison::new(metric, "ms", false, (ms(base), ms(cand)), None, thresholds));
+    }
+
+    let (base_errors, cand_errors) = (error_rate(baseline), error_rate(candidate));
+    let mut errors = MetricComparison::new("error rate", "%", false, (base_errors, cand_errors), None, thresholds);
+    errors.verdict = match cand_errors - base_errors {
+        rise if rise > ERROR_RATE_TOLERANCE => Verdict::Regression,
+        rise if rise < -ERROR_RATE_TOLERANCE => Verdict::Improvement,
+        _ => Verdict::Unchanged,
+    };
+    metrics.push(errors);
+    metrics
+}
+
+/// Settings that differ between two runs and make their numbers less comparable.
+pub fn differences(baseline: &RunRecord, candidate: &RunRecord) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut check = |name: &str, baseline: String, candidate: String| {
+        if baseline != candidate {
+            differences.push(format!("{}: {} -> {}", name, baseline, candidate));
+        }
+    };
+    let (base, cand) = (&baseline.report, &candidate.report);
+    check("backend", baseline.backend.clone(), candidate.backend.clone());
+    check("workload", base.workload.clone(), cand.workload.clone());
+    check("distribution", format!("{:?}", base.distribution), format!("{:?}", cand.distribution));
+    check("records", format!("{:?}", base.records), format!("{:?}", cand.records));
+    check("concurrency", base.concurrency.to_string(), cand.concurrency.to_string());
+    check("rate", format!("{:?}", base.rate), format!("{:?}", cand.rate));
+    check("machine", format!("{:?}", baseline.machine), format!("{:?}", candidate.machine));
+    check(
+        "server config",
+        serde_json::to_string(&baseline.config).unwrap_or_default(),
+        serde_json::to_string(&candidate.config).unwrap_or_default(),
+    );
+    differences
+}
+
+impl fmt::Display for MetricComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let p_value = self.p_value.map_or("-".to_string(), |p| format!("{:.4}", p));
+        let verdict = match self.verdict {
+            Verdict::Regression => "REGRESSION",
+            Verdict::Improvement => "improvement",
+            Verdict::Unchanged => "",
+        };
+        write!(
+            f,
+            "{:<14} {:>12.2} {:>12.2} {:<5} {:>+8.1}% {:>8} {}",
+            self.metric,
+            self.baseline,
+            self.candidate,
+            self.unit,
+            self.change * 100.0,
+            p_value,
+            verdict
+        )
+    }
+}
+
+/// Two-sided p-value of Welch's t-test for equal means, `None` with fewer than
+/// two samples on either side.
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let variance = |samples: &[f64], mean: f64| {
+        samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64
+    };
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (se_a, se_b) = (variance(a, mean_a) / a.len() as f64, variance(b, mean_b) / b.len() as f64);
+    let se = se_a + se_b;
+    if se == 0.0 {
+        // Constant samples: any difference is certain
+        return Some(if mean_a == mean_b { 1.0 } else { 0.0 });
+    }
+    let t = (mean_a - mean_b) / se.sqrt();
+    let df = se.powi(2) / (se_a.powi(2) / (a.len() - 1) as f64 + se_b.powi(2) / (b.len() - 1) as f64);
+    // P(|T| > t) for Student's t with `df` degrees of freedom
+    Some(incomplete_beta(df / 2.0, 0.5, df / (df + t * t)))
+}
+
+/// Regularized incomplete beta function I_x(a, b), after Numerical Recipes.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly only below this point
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..=300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        for numerator in [
+            m * (b - m) * x / ((a + m2 - 1.0) * (a + m2)),
+            -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// ln Γ(x) for x > 0, Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+    (2.5066282746310005 * series / x).ln() + (x + 0.5) * tmp.ln() - tmp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{Interval, KeyDistribution, LatencySummary, OperationReport};
+
+    fn report(requests: &[u64], latency_ms: f64) -> Report {
+        let total = requests.iter().sum::<u64>();
+        let timeline = requests
+            .iter()
+            .map(|requests| Interval { requests: *requests, mean_latency_us: latency_ms * 1000.0 + *requests as f64 % 7.0 })
+            .collect::<Vec<_>>();
+        let latency = LatencySummary {
+            mean_us: latency_ms * 1000.0,
+            p50_us: (latency_ms * 1000.0) as u64,
+            p99_us: (latency_ms * 2000.0) as u64,
+            ..Default::default()
+        };
+        Report {
+            workload: "read-heavy".to_string(),
+            distribution: Some(KeyDistribution::Zipfian),
+            records: Some(1000),
+            seed: Some(1),
+            url: "http://localhost:3000".to_string(),
+            concurrency: 10,
+            rate: None,
+            duration_secs: requests.len() as f64,
+            total: OperationReport {
+                requests: total,
+                successes: total,
+                transport_errors: 0,
+                status_codes: BTreeMap::from([(200, total)]),
+                requests_per_sec: total as f64 / requests.len() as f64,
+                latency,
+                service_time: None,
+                histogram: None,
+            },
+            operations: BTreeMap::new(),
+            timeline,
+        }
+    }
+
+    #[test]
+    fn test_welch_t_test() {
+        // t = -2.46 with 25.0 degrees of freedom
+        let a = [27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7, 21.4];
+        let b = [27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5, 24.4];
+        let p = welch_t_test(&a, &b).unwrap();
+        assert!((p - 0.02138).abs() < 0.00001, "{}", p);
+
+        assert!((welch_t_test(&a, &a).unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(welch_t_test(&[1.0, 1.0], &[2.0, 2.0]), Some(0.0));
+        assert_eq!(welch_t_test(&[1.0], &b), None);
+    }
+
+    #[test]
+    fn test_incomplete_beta() {
+        assert!((incomplete_beta(1.0, 1.0, 0.3) - 0.3).abs() < 1e-9);
+        // I_0.5(a, a) = 0.5 by symmetry
+        assert!((incomplete_beta(7.5, 7.5, 0.5) - 0.5).abs() < 1e-9);
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_significant_drop_is_a_regression() {
+        let baseline = report(&[1000, 1010, 990, 1005, 995, 1002, 998, 1001, 999, 1000], 5.0);
+        let candidate = report(&[800, 810, 790, 805, 795, 802, 798, 801, 799, 800], 5.0);
+        let metrics = compare_reports(&baseline, &candidate, Thresholds::default());
+
+        let throughput = &metrics[0];
+        assert_eq!(throughput.metric, "throughput");
+        assert_eq!(throughput.verdict, Verdict::Regression);
+        assert!((throughput.change + 0.2).abs() < 0.001, "{}", throughput.change);
+        assert!(throughput.p_value.unwrap() < 0.001);
+
+        let reversed = compare_reports(&candidate, &baseline, Thresholds::default());
+        assert_eq!(reversed[0].verdict, Verdict::Improvement);
+        // Latencies are the same in both runs
+        assert!(metrics[1..].iter().all(|metric| metric.verdict == Verdict::Unchanged), "{:?}", metrics);
+    }
+
+    #[test]
+    fn test_noise_is_not_a_regression() {
+        // 8% lower on average, but within the run-to-run noise
+        let baseline = report(&[1000, 1400, 600, 1300, 700, 1000], 5.0);
+        let candidate = report(&[920, 1300, 500, 1250, 600, 950], 5.0);
+        let metrics = compare_reports(&baseline, &candidate, Thresholds::default());
+        assert!(metrics[0].change < -0.05);
+        assert!(metrics[0].p_value.unwrap() > 0.05);
+        assert_eq!(metrics[0].verdict, Verdict::Unchanged);
+
+        // Small but consistent changes stay below `min_change`
+        let candidate = report(&[990, 1390, 590, 1290, 690, 990], 5.1);
+        let metrics = compare_reports(&baseline, &candidate, Thresholds::default());
+        assert!(metrics.iter().all(|metric| metric.verdict == Verdict::Unchanged), "{:?}", metrics);
+    }
+
+    #[test]
+    fn test_percentiles_use_thresholds() {
+        let baseline = report(&[1000, 1000], 5.0);
+        let candidate = report(&[1000, 1000], 6.0);
+        let metrics = compare_reports(&baseline, &candidate, Thresholds::default());
+        let p99 = metrics.iter().find(|metric| metric.metric == "p99 latency").unwrap();
+        assert_eq!((p99.baseline, p99.candidate), (10.0, 12.0));
+        assert_eq!(p99.p_value, None);
+        assert_eq!(p99.verdict, Verdict::Regression);
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = std::env::temp_dir().join(format!("results_store_{}", std::process::id()));
+        let store = ResultsStore::new(&dir);
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.load("latest").is_err());
+
+        let first = store.save("sqlite", report(&[100, 100], 1.0), Some(Config::default())).unwrap();
+        let second = store.save("my backend", report(&[90, 90], 1.0), None).unwrap();
+        assert!(first.ends_with("-sqlite-read-heavy"), "{}", first);
+        assert!(second.ends_with("-my_backend-read-heavy"), "{}", second);
+
+        let runs = store.list().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(store.load("latest").unwrap().id, runs[1].id);
+        let record = store.load(&first).unwrap();
+        assert_eq!(record.report.timeline.len(), 2);
+        assert!(record.config.is_some());
+        assert!(record.machine.cpus >= 1);
+
+        assert!(store.baseline("sqlite", "read-heavy").unwrap().is_none());
+        store.set_baseline(&first).unwrap();
+        assert_eq!(store.baseline("sqlite", "read-heavy").unwrap().unwrap().id, first);
+        assert!(store.baseline("my_backend", "read-heavy").unwrap().is_none());
+
+        let differences = differences(&record, &store.load(&second).unwrap());
+        assert!(differences.iter().any(|difference| difference.starts_with("backend: sqlite -> my_backend")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
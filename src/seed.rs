@@ -0,0 +1,144 @@
+//! The `seed` subcommand, which replaces the users of a backend with a
+//! deterministic dataset so every benchmark starts from the same state.
+//!
+//! Users are named `user{start}` to `user{start + users - 1}`, the names that
+//! `get.lua`, `update.lua` and the bench workloads read, with an age, email,
+//! display name and attributes derived from the number in their name, so rows
+//! are about the size of real ones.
+
+use anyhow::Context;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use crate::database::{Database, NewUser};
+
+/// Flags of the `seed` subcommand.
+#[derive(Clone, Debug, clap::Args)]
+pub struct SeedArgs {
+    /// Users to create
+    #[arg(long, short = 'n', default_value_t = 10_000)]
+    pub users: u64,
+    /// Number N of the first `userN`, like the bench `--start`
+    #[arg(long, default_value_t = 1)]
+    pub start: u64,
+    /// Users sent to the backend's bulk insert at once
+    #[arg(long, default_value_t = 10_000)]
+    pub batch_size: u64,
+}
+
+/// The seeded user numbered `n`.
+pub fn user(n: u64) -> NewUser {
+    let locale = ["en-US", "de-DE", "fr-FR", "ja-JP"][(n % 4) as usize];
+    let tier = ["free", "pro", "team"][(n % 3) as usize];
+    let attributes = json!({
+        "locale": locale,
+        "newsletter": n.is_multiple_of(3),
+        "plan": { "tier": tier, "seats": n % 10 + 1 },
+        "tags": [format!("cohort-{}", n % 12), format!("region-{}", n % 5)],
+    });
+    NewUser {
+        username: format!("user{}", n),
+        age: (n % 100) as u32,
+        email: Some(format!("user{}@example.com", n)),
+        display_name: Some(format!("User {}", n)),
+        attributes: attributes.as_object().cloned().unwrap_or_default(),
+    }
+}
+
+/// Outcome of `seed`.
+#[derive(Debug)]
+pub struct Seeded {
+    /// Users counted in the backend once done, equal to `SeedArgs::users`.
+    pub users: u64,
+    pub elapsed: Duration,
+}
+
+/// Deletes every user, bulk-inserts the seeded ones and checks that exactly
+/// those are there.
+pub async fn seed<T: Database>(db: &T, args: &SeedArgs) -> anyhow::Result<Seeded> {
+    anyhow::ensure!(args.batch_size > 0, "batch size must be at least 1");
+    let started = Instant::now();
+
+    db.delete_all_users().await.context("Failed to delete existing users")?;
+
+    let end = args.start + args.users;
+    for batch_start in (args.start..end).step_by(args.batch_size as usize) {
+        let batch_end = end.min(batch_start + args.batch_size);
+        let users = (batch_start..batch_end).map(user).collect();
+        db.insert_users(users)
+            .await
+            .with_context(|| format!("Failed to insert user{} to user{}", batch_start, batch_end - 1))?;
+    }
+
+    let count = db.count_users().await.context("Failed to count users")?;
+    anyhow::ensure!(count == args.users, "Expected {} users after seeding, found {}", args.users, count);
+    // The count alone would not notice users stored under the wrong name or values
+    for n in [args.start, end - 1].into_iter().filter(|_| args.users > 0) {
+        let expected = user(n);
+        let stored = db.get_user(expected.username.clone()).await
+            .with_context(|| format!("Failed to read back {}", expected.username))?;
+        anyhow::ensure!(stored.age == expected.age, "{} has age {} instead of {}", expected.username, stored.age, expected.age);
+        anyhow::ensure!(
+            stored.email == expected.email && stored.attributes == expected.attributes,
+            "{} has different email or attributes than seeded", expected.username,
+        );
+    }
+
+    Ok(Seeded {
+        users: count,
+        elapsed: started.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::database::CreateUser;
+    use crate::databases::{InMemoryDatabase, SqliteDatabase};
+
+    fn args(users: u64, batch_size: u64) -> SeedArgs {
+        SeedArgs { users, start: 1, batch_size }
+    }
+
+    async fn seed_replaces_users<T: Database>() {
+        let db = T::init(&Config::for_tests()).await.map_err(Into::into).unwrap();
+        db.create_user(CreateUser { username: "hello".to_string() }).await.map_err(Into::into).unwrap();
+
+        // Batches that don't divide the users evenly
+        let seeded = seed(&db, &args(25, 10)).await.unwrap();
+        assert_eq!(seeded.users, 25);
+        assert!(db.get_user("hello".to_string()).await.is_err());
+        let user = db.get_user("user17".to_string()).await.map_err(Into::into).unwrap();
+        assert_eq!(user.age, 17);
+        assert_eq!(user.display_name.as_deref(), Some("User 17"));
+        assert_eq!(user.attributes["plan"]["tier"], "team");
+
+        // Seeding again starts over rather than conflicting
+        seed(&db, &args(5, 100)).await.unwrap();
+        assert_eq!(db.count_users().await.map_err(Into::into), Ok(5));
+        assert!(db.get_user("user6".to_string()).await.is_err());
+
+        seed(&db, &args(0, 100)).await.unwrap();
+        assert_eq!(db.count_users().await.map_err(Into::into), Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_seed_in_memory() {
+        seed_replaces_users::<InMemoryDatabase>().await;
+    }
+
+    #[tokio::test]
+    async fn test_seed_sqlite() {
+        seed_replaces_users::<SqliteDatabase>().await;
+    }
+
+    #[test]
+    fn test_users_are_deterministic() {
+        assert_eq!(user(1), user(1));
+        assert_eq!(user(1).username, "user1");
+        assert_eq!(user(1).email.as_deref(), Some("user1@example.com"));
+        assert_eq!(user(1234).age, 34);
+        assert_eq!(user(1234).attributes["plan"]["seats"], 5);
+    }
+}
@@ -0,0 +1,930 @@
+//! HTTP routes of the benchmark server, generic over the `Database` backend.
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderName, StatusCode, header},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, patch, post, put},
+};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::config::LoggingConfig;
+use crate::database::{BatchUpdate, CreateUser, Database, ListUsers, UpdateUser, User, UserCursor};
+use crate::err::ServerError;
+use crate::logging::RequestLogger;
+use crate::metrics::{self, Instrumented};
+
+#[derive(Clone)]
+pub struct AppState<T: Database> {
+    pub db: T,
+}
+
+/// Page size of `GET /users` without a `limit`.
+const DEFAULT_PAGE_SIZE: u32 = 100;
+/// Largest `limit` of `GET /users`.
+const MAX_PAGE_SIZE: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UserOrder {
+    #[default]
+    Id,
+    Username,
+}
+
+/// Query of `GET /users`: `cursor` is the `next_cursor` of the previous page.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListUsersQuery {
+    #[serde(default)]
+    pub order: UserOrder,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Largest number of items of a batch route.
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// Result of one item of a batch route, in the order of the request.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BatchItem {
+    pub username: String,
+    /// The status code a single-user request would have answered.
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchItem {
+    fn new(username: String, result: Result<Option<User>, ServerError>) -> Self {
+        match result {
+            Ok(user) => BatchItem { username, status: StatusCode::OK.as_u16(), user, error: None },
+            Err(e) => BatchItem { username, status: e.status_code().as_u16(), user: None, error: Some(e.to_string()) },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    /// Cursor of the next page, or `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// The user routes, logging requests through `tracing` as `logging` selects,
+/// plus the Prometheus metrics of the requests and of the backend on `/metrics`.
+pub fn router<T: Database + 'static>(state: AppState<T>, logging: &LoggingConfig) -> Router {
+    routes(AppState { db: Instrumented::new(state.db) }, logging)
+}
+
+fn routes<T: Database + 'static>(state: AppState<T>, logging: &LoggingConfig) -> Router {
+    Router::new()
+        // `GET /` goes to `root`
+        .route("/", get(root::<T>))
+        // `GET /users/{username}` goes to `get_user_by_username`
+        .route("/users/{username}", get(get_user_by_username::<T>))
+        // `GET /users` goes to `list_users`
+        .route("/users", get(list_users::<T>))
+        // `POST /users` goes to `create_user`
+        .route("/users", post(create_user::<T>))
+        // `POST /users/batch` goes to `create_users`
+        .route("/users/batch", post(create_users::<T>))
+        // `POST /users/batch/get` goes to `get_users`
+        .route("/users/batch/get", post(get_users::<T>))
+        // `PATCH /users/batch` goes to `update_users`
+        .route("/users/batch", patch(update_users::<T>))
+        // `DELETE /users/batch` goes to `delete_users`
+        .route("/users/batch", delete(delete_users::<T>))
+        // `PATCH /users/{username}` goes to `update_user_by_username`
+        .route("/users/{username}", patch(update_user_by_username::<T>))
+        // `PUT /users/{username}` goes to `upsert_user_by_username`
+        .route("/users/{username}", put(upsert_user_by_username::<T>))
+        // `DELETE /users/{username}` goes to `delete_user_by_username`
+        .route("/users/{username}", delete(delete_user_by_username::<T>))
+        // Scrapes are left out of the request metrics
+        .route_layer(middleware::from_fn(metrics::track_http))
+        .route("/metrics", get(export_metrics::<T>))
+        .with_state(state)
+        .layer(RequestLogger::layer(logging))
+}
+
+// basic handler that responds with a static string
+async fn root<T: Database>(State(_): State<AppState<T>>) -> &'static str {
+    "Hello, World!"
+}
+async fn export_metrics<T: Database>(State(state): State<AppState<T>>) -> Result<impl IntoResponse, ServerError> {
+    let metrics = metrics::metrics();
+    // Pool state is read at scrape time rather than tracked on every checkout
+    if let Some(status) = state.db.pool_status() {
+        metrics.set_pool_status(T::NAME, &status);
+    }
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics.render()?))
+}
+
+async fn create_user<T: Database>(
+    State(state): State<AppState<T>>,
+    Json(payload): Json<CreateUser>,
+) -> Result<String, ServerError> {
+    state.db.create_user(payload).await.map_err(Into::into)
+}
+
+async fn list_users<T: Database>(
+    State(state): State<AppState<T>>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<UserPage>, ServerError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ServerError::Validation(format!("limit must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+    let cursor = match query.order {
+        UserOrder::Id => {
+            let after = query.cursor.map(|cursor| cursor.parse::<u64>()).transpose()
+                .map_err(|_| ServerError::Validation("cursor must be a user id when ordering by id".to_string()))?;
+            UserCursor::Id(after)
+        }
+        UserOrder::Username => UserCursor::Username(query.cursor),
+    };
+
+    let users = state.db.list_users(ListUsers { cursor: cursor.clone(), limit }).await.map_err(Into::into)?;
+    // A full page may be followed by more users, a short one is the last
+    let next_cursor = match users.last() {
+        Some(last) if users.len() == limit as usize => match cursor.after(last) {
+            UserCursor::Id(id) => id.map(|id| id.to_string()),
+            UserCursor::Username(username) => username,
+        },
+        _ => None,
+    };
+    Ok(Json(UserPage { users, next_cursor }))
+}
+
+/// Rejects batches the backends are not meant to take: oversized ones, and
+/// ones naming a user twice.
+fn check_batch<'a>(usernames: impl ExactSizeIterator<Item = &'a String>) -> Result<(), ServerError> {
+    if usernames.len() > MAX_BATCH_SIZE {
+        return Err(ServerError::Validation(format!("A batch takes at most {} users", MAX_BATCH_SIZE)));
+    }
+    let mut seen = HashSet::new();
+    for username in usernames {
+        if !seen.insert(username) {
+            return Err(ServerError::Validation(format!("User appears twice in the batch: {}", username)));
+        }
+    }
+    Ok(())
+}
+
+/// Pairs the results of a batch method with the usernames of its items.
+fn batch_items<R, E: Into<ServerError>>(
+    usernames: Vec<String>,
+    results: Vec<Result<R, E>>,
+    user: impl Fn(R) -> Option<User>,
+) -> Json<Vec<BatchItem>> {
+    let items = usernames.into_iter().zip(results);
+    Json(items.map(|(username, result)| BatchItem::new(username, result.map(&user).map_err(Into::into))).collect())
+}
+
+async fn create_users<T: Database>(
+    State(state): State<AppState<T>>,
+    Json(users): Json<Vec<CreateUser>>,
+) -> Result<Json<Vec<BatchItem>>, ServerError> {
+    check_batch(users.iter().map(|user| &user.username))?;
+    let usernames: Vec<String> = users.iter().map(|user| user.username.clone()).collect();
+    let results = state.db.create_users(users).await.map_err(Into::into)?;
+    Ok(batch_items(usernames, results, |()| None))
+}
+
+async fn get_users<T: Database>(
+    State(state): State<AppState<T>>,
+    Json(usernames): Json<Vec<String>>,
+) -> Result<Json<Vec<BatchItem>>, ServerError> {
+    check_batch(usernames.iter())?;
+    let results = state.db.get_users(usernames.clone()).await.map_err(Into::into)?;
+    Ok(batch_items(usernames, results, Some))
+}
+
+async fn update_users<T: Database>(
+    State(state): State<AppState<T>>,
+    Json(updates): Json<Vec<BatchUpdate>>,
+) -> Result<Json<Vec<BatchItem>>, ServerError> {
+    check_batch(updates.iter().map(|item| &item.username))?;
+    let usernames: Vec<String> = updates.iter().map(|item| item.username.clone()).collect();
+    let results = state.db.update_users(updates).await.map_err(Into::into)?;
+    Ok(batch_items(usernames, results, |()| None))
+}
+
+async fn delete_users<T: Database>(
+    State(state): State<AppState<T>>,
+    Json(usernames): Json<Vec<String>>,
+) -> Result<Json<Vec<BatchItem>>, ServerError> {
+    check_batch(usernames.iter())?;
+    let results = state.db.delete_users(usernames.clone()).await.map_err(Into::into)?;
+    Ok(batch_items(usernames, results, |()| None))
+}
+
+/// The `ETag` header of a user at `version`, which is the version as a strong tag.
+fn etag(version: u64) -> [(HeaderName, String); 1] {
+    [(header::ETAG, format!("\"{}\"", version))]
+}
+
+/// The versions the comma-separated tags of `If-Match` allow the user to be
+/// at, or `None` without the header or with `*`. Weak tags and anything but
+/// our tags match no version, so they are left out.
+fn if_match(headers: &HeaderMap) -> Option<Vec<u64>> {
+    let values: Vec<&str> =
+        headers.get_all(header::IF_MATCH).iter().map(|value| value.to_str().unwrap_or_default()).collect();
+    if values.is_empty() || values.iter().any(|value| value.trim() == "*") {
+        return None;
+    }
+    let versions = values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .filter_map(|tag| tag.trim().strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+        .collect();
+    Some(versions)
+}
+
+/// The version to make the write on `username` conditional on for
+/// `If-Match`. The store takes one, so of several listed versions this is the
+/// one the user is at, which the store then checks again with the write.
+/// Fails as the store would when no listed version can match.
+async fn expected_version<T: Database>(db: &T, username: &str, headers: &HeaderMap) -> Result<Option<u64>, ServerError> {
+    let Some(versions) = if_match(headers) else {
+        return Ok(None);
+    };
+    let version = match versions[..] {
+        [] => None,
+        [version] => Some(version),
+        _ => {
+            let current = db.get_user(username.to_string()).await.map_err(Into::into)?.version;
+            versions.contains(&current).then_some(current)
+        }
+    };
+    version
+        .map(Some)
+        .ok_or_else(|| ServerError::PreconditionFailed(format!("User version mismatch: {}", username)))
+}
+
+pub async fn get_user_by_username<T: Database>(
+    State(state): State<AppState<T>>,
+    Path(username): Path<String>,
+) -> Result<([(HeaderName, String); 1], Json<User>), ServerError> {
+    let user = state.db.get_user(username).await.map_err(Into::into)?;
+    Ok((etag(user.version), Json(user)))
+}
+
+/// Answers with the `ETag` of the updated user. With `If-Match`, only updates
+/// a user still at one of the versions listed.
+async fn update_user_by_username<T: Database>(
+    State(state): State<AppState<T>>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateUser>,
+) -> Result<(StatusCode, [(HeaderName, String); 1]), ServerError> {
+    let expected = expected_version(&state.db, &username, &headers).await?;
+    let version = state.db.update_user(username, payload, expected).await.map_err(Into::into)?;
+    Ok((StatusCode::OK, etag(version)))
+}
+
+/// Answers `201 Created` if the user was created, `200 OK` if updated, with
+/// the user as stored.
+async fn upsert_user_by_username<T: Database>(
+    State(state): State<AppState<T>>,
+    Path(username): Path<String>,
+    Json(payload): Json<UpdateUser>,
+) -> Result<(StatusCode, Json<User>), ServerError> {
+    let upserted = state.db.upsert_user(username, payload).await.map_err(Into::into)?;
+    let status = if upserted.created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(upserted.user)))
+}
+
+/// With `If-Match`, only deletes a user still at one of the versions listed.
+pub async fn delete_user_by_username<T: Database>(
+    State(state): State<AppState<T>>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ServerError> {
+    let expected = expected_version(&state.db, &username, &headers).await?;
+    state.db.delete_user(username, expected).await.map_err(Into::into)?;
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use crate::config::Config;
+    use crate::databases::SqliteDatabase;
+
+    async fn create_test_state() -> AppState<SqliteDatabase> {
+        // Every test gets its own private in-memory database
+        let mut config = Config::default();
+        config.sqlite.path = ":memory:".to_string();
+        AppState { db: SqliteDatabase::init(&config).await.unwrap() }
+    }
+
+    #[tokio::test]
+    async fn test_root() {
+        let state = create_test_state().await;
+        let response = root(State(state)).await;
+        assert_eq!(response, "Hello, World!");
+    }
+    
+    #[tokio::test]
+    async fn test_create_user() {
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        let state = create_test_state().await;
+        let status = create_user(State(state), Json(payload)).await;
+        assert_eq!(
+            status,
+            Ok("User created with username: testuser".to_string())
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_get_user_by_username() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        let response = get_user_by_username(State(state), Path("testuser".to_string())).await;
+        assert!(response.is_ok());
+        let user = response.unwrap().1;
+        assert_eq!(user.username, "testuser");
+    }
+
+    #[tokio::test]
+    async fn test_update_user_by_username() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        let update_payload = UpdateUser::with_age(30);
+        let response = update_user_by_username(
+            State(state.clone()),
+            Path("testuser".to_string()),
+            HeaderMap::new(),
+            Json(update_payload),
+        )
+        .await;
+
+        assert!(response.is_ok());
+        assert_eq!(response.unwrap().0, StatusCode::OK);
+
+        // Verify the update
+        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
+        let user = user_response.unwrap().1;
+        assert_eq!(user.username, "testuser");
+        assert_eq!(user.age, 30);
+    }
+    
+    #[tokio::test]
+    async fn test_delete_user_by_username() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        let response =
+            delete_user_by_username(State(state.clone()), Path("testuser".to_string()), HeaderMap::new()).await;
+        assert!(response.is_ok());
+        assert_eq!(response.unwrap(), StatusCode::OK);
+
+        // Verify the deletion
+        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
+        assert!(user_response.is_err());
+    }
+
+    // FAILURE SCENARIO TESTS
+    #[tokio::test]
+    async fn test_get_nonexistent_user() {
+        let state = create_test_state().await;
+        
+        let response = get_user_by_username(State(state), Path("nonexistent".to_string())).await;
+        assert!(response.is_err());
+        
+        let error = response.unwrap_err();
+        assert!(matches!(error, ServerError::NotFound(_)));
+    }
+
+    #[tokio::test] 
+    async fn test_update_nonexistent_user() {
+        let state = create_test_state().await;
+        let update_payload = UpdateUser::with_age(25);
+        
+        let response = update_user_by_username(
+            State(state),
+            Path("nonexistent".to_string()),
+            HeaderMap::new(),
+            Json(update_payload),
+        ).await;
+        
+        // Every backend reports a missing user instead of silently updating nothing
+        assert_eq!(
+            response,
+            Err(ServerError::NotFound("User not found: nonexistent".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_nonexistent_user() {
+        let state = create_test_state().await;
+        
+        let response = delete_user_by_username(State(state), Path("nonexistent".to_string()), HeaderMap::new()).await;
+        
+        // Every backend reports a missing user instead of silently deleting nothing
+        assert_eq!(
+            response,
+            Err(ServerError::NotFound("User not found: nonexistent".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_user_with_unchanged_age() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        // Writing the same value twice still matches the row, and bumps its version
+        for version in [2, 3] {
+            let response = update_user_by_username(
+                State(state.clone()),
+                Path("testuser".to_string()),
+                HeaderMap::new(),
+                Json(UpdateUser::with_age(7)),
+            ).await;
+            assert_eq!(response, Ok((StatusCode::OK, etag(version))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_twice() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        let first = delete_user_by_username(State(state.clone()), Path("testuser".to_string()), HeaderMap::new()).await;
+        assert_eq!(first, Ok(StatusCode::OK));
+
+        let second = delete_user_by_username(State(state), Path("testuser".to_string()), HeaderMap::new()).await;
+        assert!(matches!(second, Err(ServerError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_duplicate_user() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "duplicate_user".to_string(),
+        };
+        
+        // Create the user first time - should succeed
+        let first_response = create_user(State(state.clone()), Json(payload.clone())).await;
+        assert!(first_response.is_ok());
+        
+        // Try to create the same user again - should fail due to UNIQUE constraint
+        let second_response = create_user(State(state), Json(payload)).await;
+        assert!(second_response.is_err());
+        
+        let error = second_response.unwrap_err();
+        assert!(matches!(error, ServerError::Conflict(_)));
+    }
+
+    #[tokio::test]
+    async fn test_error_status_codes() {
+        let state = create_test_state().await;
+
+        let response = get_user_by_username(State(state.clone()), Path("nonexistent".to_string())).await;
+        assert_eq!(response.unwrap_err().into_response().status(), StatusCode::NOT_FOUND);
+
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload.clone())).await.unwrap();
+        let response = create_user(State(state), Json(payload)).await;
+        assert_eq!(response.unwrap_err().into_response().status(), StatusCode::CONFLICT);
+
+        let statuses = [
+            (ServerError::PreconditionFailed(String::new()), StatusCode::PRECONDITION_FAILED),
+            (ServerError::Validation(String::new()), StatusCode::UNPROCESSABLE_ENTITY),
+            (ServerError::Unavailable(String::new()), StatusCode::SERVICE_UNAVAILABLE),
+            (ServerError::Timeout(String::new()), StatusCode::GATEWAY_TIMEOUT),
+            (ServerError::Internal(String::new()), StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (error, status) in statuses {
+            assert_eq!(error.into_response().status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_user_empty_username() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "".to_string(),
+        };
+        
+        let response = create_user(State(state), Json(payload)).await;
+        // Empty username should be allowed by current implementation
+        // The database will accept empty strings as valid usernames
+        assert!(response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_user_with_large_age() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        // Test with maximum u32 value
+        let update_payload = UpdateUser::with_age(u32::MAX);
+        let response = update_user_by_username(
+            State(state.clone()),
+            Path("testuser".to_string()),
+            HeaderMap::new(),
+            Json(update_payload),
+        ).await;
+        
+        assert!(response.is_ok());
+        assert_eq!(response.unwrap().0, StatusCode::OK);
+        
+        // Verify the update worked
+        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
+        assert!(user_response.is_ok());
+        let user = user_response.unwrap().1;
+        assert_eq!(user.age, u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_very_long_username() {
+        let state = create_test_state().await;
+        // Create a very long username (1000 characters)
+        let long_username = "a".repeat(1000);
+        let payload = CreateUser {
+            username: long_username.clone(),
+        };
+        
+        let response = create_user(State(state.clone()), Json(payload)).await;
+        assert!(response.is_ok());
+        
+        // Verify we can retrieve the user with long username
+        let user_response = get_user_by_username(State(state), Path(long_username)).await;
+        assert!(user_response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_special_characters() {
+        let state = create_test_state().await;
+        let special_username = "user@#$%^&*()_+-=[]{}|;:,.<>?".to_string();
+        let payload = CreateUser {
+            username: special_username.clone(),
+        };
+        
+        let response = create_user(State(state.clone()), Json(payload)).await;
+        assert!(response.is_ok());
+        
+        // Verify we can retrieve the user with special characters
+        let user_response = get_user_by_username(State(state), Path(special_username)).await;
+        assert!(user_response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_user_zero_age() {
+        let state = create_test_state().await;
+        let payload = CreateUser {
+            username: "testuser".to_string(),
+        };
+        create_user(State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+
+        // Test with zero age
+        let update_payload = UpdateUser::with_age(0);
+        let response = update_user_by_username(
+            State(state.clone()),
+            Path("testuser".to_string()),
+            HeaderMap::new(),
+            Json(update_payload),
+        ).await;
+        
+        assert!(response.is_ok());
+        assert_eq!(response.unwrap().0, StatusCode::OK);
+        
+        // Verify the update worked
+        let user_response = get_user_by_username(State(state), Path("testuser".to_string())).await;
+        assert!(user_response.is_ok());
+        let user = user_response.unwrap().1;
+        assert_eq!(user.age, 0);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_operations_on_same_user() {
+        let state = create_test_state().await;
+        let username = "multiop_user".to_string();
+        
+        // Create user
+        let create_payload = CreateUser {
+            username: username.clone(),
+        };
+        let create_response = create_user(State(state.clone()), Json(create_payload)).await;
+        assert!(create_response.is_ok());
+        
+        // Get user
+        let get_response = get_user_by_username(State(state.clone()), Path(username.clone())).await;
+        assert!(get_response.is_ok());
+        let user = get_response.unwrap().1;
+        assert_eq!(user.username, username);
+        assert_eq!(user.age, 0); // Default age
+        
+        // Update user
+        let update_payload = UpdateUser::with_age(42);
+        let update_response = update_user_by_username(
+            State(state.clone()),
+            Path(username.clone()),
+            HeaderMap::new(),
+            Json(update_payload),
+        ).await;
+        assert!(update_response.is_ok());
+        
+        // Get user again to verify update
+        let get_response2 = get_user_by_username(State(state.clone()), Path(username.clone())).await;
+        assert!(get_response2.is_ok());
+        let user2 = get_response2.unwrap().1;
+        assert_eq!(user2.age, 42);
+        
+        // Delete user
+        let delete_response = delete_user_by_username(State(state.clone()), Path(username.clone()), HeaderMap::new()).await;
+        assert!(delete_response.is_ok());
+        
+        // Try to get user after deletion - should fail
+        let get_response3 = get_user_by_username(State(state), Path(username)).await;
+        assert!(get_response3.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_users() {
+        let state = create_test_state().await;
+        for username in ["carol", "alice", "bob"] {
+            create_user(State(state.clone()), Json(CreateUser { username: username.to_string() })).await.unwrap();
+        }
+        let list = |order, cursor: Option<&str>, limit| {
+            let query = ListUsersQuery { order, cursor: cursor.map(str::to_string), limit };
+            list_users(State(state.clone()), Query(query))
+        };
+        let usernames = |page: &UserPage| page.users.iter().map(|user| user.username.clone()).collect::<Vec<_>>();
+
+        let page = list(UserOrder::Id, None, Some(2)).await.unwrap().0;
+        assert_eq!(usernames(&page), ["carol", "alice"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("2"));
+        let page = list(UserOrder::Id, Some("2"), Some(2)).await.unwrap().0;
+        assert_eq!(usernames(&page), ["bob"]);
+        assert_eq!(page.next_cursor, None);
+
+        let page = list(UserOrder::Username, None, Some(2)).await.unwrap().0;
+        assert_eq!(usernames(&page), ["alice", "bob"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("bob"));
+        let page = list(UserOrder::Username, Some("bob"), None).await.unwrap().0;
+        assert_eq!(usernames(&page), ["carol"]);
+
+        for (order, cursor, limit) in [
+            (UserOrder::Id, None, Some(0)),
+            (UserOrder::Id, None, Some(MAX_PAGE_SIZE + 1)),
+            (UserOrder::Id, Some("bob"), None),
+        ] {
+            let error = list(order, cursor, limit).await.unwrap_err();
+            assert!(matches!(error, ServerError::Validation(_)), "{:?}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_users_query() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(create_test_state().await, &LoggingConfig::default());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        for username in ["a b", "c"] {
+            let response = client
+                .post(format!("{}/users", url))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(serde_json::json!({ "username": username }).to_string())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = client.get(format!("{}/users?order=username&cursor=a%20b&limit=1", url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(page["users"][0]["username"], "c");
+        assert_eq!(page["next_cursor"], "c");
+
+        let response = client.get(format!("{}/users?order=age", url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_user_by_username() {
+        let state = create_test_state().await;
+        let upsert = |age| upsert_user_by_username(State(state.clone()), Path("testuser".to_string()), Json(UpdateUser::with_age(age)));
+
+        let (status, Json(created)) = upsert(20).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!((created.username.as_str(), created.age), ("testuser", 20));
+
+        let (status, Json(updated)) = upsert(21).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((updated.id, updated.age), (created.id, 21));
+        let user = get_user_by_username(State(state.clone()), Path("testuser".to_string())).await.unwrap().1;
+        assert_eq!(user.age, 21);
+    }
+
+    #[tokio::test]
+    async fn test_if_match() {
+        let state = create_test_state().await;
+        create_user(State(state.clone()), Json(CreateUser { username: "testuser".to_string() })).await.unwrap();
+        let if_match = |etag: &str| HeaderMap::from_iter([(header::IF_MATCH, etag.parse().unwrap())]);
+        let update = |headers| {
+            update_user_by_username(State(state.clone()), Path("testuser".to_string()), headers, Json(UpdateUser::with_age(5)))
+        };
+
+        let (headers, _) = get_user_by_username(State(state.clone()), Path("testuser".to_string())).await.unwrap();
+        assert_eq!(headers, [(header::ETAG, "\"1\"".to_string())]);
+        assert_eq!(update(if_match("\"1\"")).await, Ok((StatusCode::OK, etag(2))));
+        // Stale, weak and malformed tags match no version
+        for stale in ["\"1\"", "W/\"2\"", "2", "\"1\", W/\"2\"", "\"1\", \"3\""] {
+            assert!(matches!(update(if_match(stale)).await, Err(ServerError::PreconditionFailed(_))), "{}", stale);
+        }
+        // Any tag of a list may match
+        assert_eq!(update(if_match("\"1\", \"2\"")).await, Ok((StatusCode::OK, etag(3))));
+        assert_eq!(update(if_match("*")).await, Ok((StatusCode::OK, etag(4))));
+
+        let delete = |etag| delete_user_by_username(State(state.clone()), Path("testuser".to_string()), if_match(etag));
+        assert!(matches!(delete("\"3\"").await, Err(ServerError::PreconditionFailed(_))));
+        assert_eq!(delete("\"4\", W/\"5\"").await, Ok(StatusCode::OK));
+        assert!(matches!(delete("\"4\"").await, Err(ServerError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_batch_routes() {
+        let state = create_test_state().await;
+        create_user(State(state.clone()), Json(CreateUser { username: "taken".to_string() })).await.unwrap();
+        let usernames = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let statuses = |items: Vec<BatchItem>| items.iter().map(|item| item.status).collect::<Vec<_>>();
+
+        let users = usernames(&["alice", "taken"]).into_iter().map(|username| CreateUser { username }).collect();
+        let items = create_users(State(state.clone()), Json(users)).await.unwrap().0;
+        assert_eq!(items[1].error.as_deref(), Some("User already exists: taken"));
+        assert_eq!(statuses(items), [200, 409]);
+
+        let items = get_users(State(state.clone()), Json(usernames(&["missing", "alice"]))).await.unwrap().0;
+        assert_eq!(items[1].user.as_ref().map(|user| user.username.as_str()), Some("alice"));
+        assert_eq!(statuses(items), [404, 200]);
+
+        let updates = vec![BatchUpdate { username: "alice".to_string(), update: UpdateUser::with_age(3) }];
+        let items = update_users(State(state.clone()), Json(updates)).await.unwrap().0;
+        assert_eq!(statuses(items), [200]);
+        let user = get_user_by_username(State(state.clone()), Path("alice".to_string())).await.unwrap().1;
+        assert_eq!(user.age, 3);
+
+        let items = delete_users(State(state.clone()), Json(usernames(&["alice", "alice"]))).await;
+        assert!(matches!(items, Err(ServerError::Validation(_))));
+        let items = delete_users(State(state.clone()), Json(usernames(&["alice", "missing"]))).await.unwrap().0;
+        assert_eq!(statuses(items), [200, 404]);
+
+        let oversized = (0..=MAX_BATCH_SIZE).map(|n| format!("user{}", n)).collect();
+        let items = get_users(State(state), Json(oversized)).await;
+        assert!(matches!(items, Err(ServerError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_batch_routes_match_before_usernames() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(create_test_state().await, &LoggingConfig::default());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/users/batch", url))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(r#"[{"username": "a"}, {"username": "b"}]"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!([{ "username": "a", "status": 200 }, { "username": "b", "status": 200 }]));
+
+        let response = client
+            .patch(format!("{}/users/batch", url))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(r#"[{"username": "a", "age": 40}]"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get(format!("{}/users/a", url)).send().await.unwrap();
+        let user: User = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(user.age, 40);
+    }
+
+    #[tokio::test]
+    async fn test_patch_merges_json() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(create_test_state().await, &LoggingConfig::default());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let patch = |body: &'static str| {
+            client
+                .patch(format!("{}/users/merge", url))
+                .header(header::CONTENT_TYPE, "application/merge-patch+json")
+                .body(body)
+                .send()
+        };
+        let response = client
+            .put(format!("{}/users/merge", url))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(r#"{"email": "merge@example.com", "attributes": {"plan": {"tier": "pro", "seats": 2}}}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = patch(r#"{"display_name": "Merge", "attributes": {"plan": {"seats": null}, "locale": "en-US"}}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get(format!("{}/users/merge", url)).send().await.unwrap();
+        let user: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(user["email"], "merge@example.com");
+        assert_eq!(user["display_name"], "Merge");
+        assert_eq!(user["attributes"], serde_json::json!({ "plan": { "tier": "pro" }, "locale": "en-US" }));
+
+        let response = patch(r#"{"email": null, "attributes": null}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get(format!("{}/users/merge", url)).send().await.unwrap();
+        let user: User = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!((user.email, user.display_name.as_deref()), (None, Some("Merge")));
+        assert!(user.attributes.is_empty());
+        assert!(user.updated_at >= user.created_at);
+
+        // The age can be left out but not cleared
+        let response = patch(r#"{"age": null}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(create_test_state().await, &LoggingConfig::default());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let response = client.get(format!("{}/users/nobody", url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = client.get(format!("{}/metrics", url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], prometheus::TEXT_FORMAT);
+
+        let body = response.text().await.unwrap();
+        for expected in [
+            r#"http_requests_total{method="GET",route="/users/{username}",status="404"}"#,
+            r#"http_request_duration_seconds_bucket{method="GET",route="/users/{username}",le="0.001"}"#,
+            r#"db_operations_total{backend="sqlite",operation="get_user",result="not_found"}"#,
+            r#"db_pool_connections{backend="sqlite",state="idle"} 1"#,
+            r#"db_pool_max_connections{backend="sqlite"} 1"#,
+        ] {
+            assert!(body.contains(expected), "missing {} in:\n{}", expected, body);
+        }
+        assert!(!body.contains(r#"route="/metrics""#));
+    }
+}
@@ -0,0 +1,150 @@
+//! Logs on stdout plus, when `telemetry.otlp_endpoint` is set, export of the
+//! `tracing` spans to an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! Spans follow the OpenTelemetry conventions through the fields
+//! `tracing-opentelemetry` maps: `otel.name`, `otel.kind` and `otel.status_code`.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::io::IsTerminal;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::prelude::*;
+
+use crate::config::{Config, OtlpProtocol, TelemetryConfig};
+use crate::logging;
+
+/// Keeps the exporter of `init` alive, flushing it on `shutdown`.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Sends the spans still buffered, which would be lost on exit otherwise.
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            // Blocks until the collector answered
+            if let Err(e) = tokio::task::block_in_place(|| provider.shutdown()) {
+                eprintln!("Failed to export the last traces: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber: INFO logs to stdout unless logging is off
+/// and, when configured, INFO spans to the OTLP collector.
+pub fn init(config: &Config) -> anyhow::Result<Telemetry> {
+    let telemetry = &config.telemetry;
+    let provider = telemetry.otlp_endpoint.as_ref().map(|_| tracer_provider(telemetry)).transpose()?;
+    let otel = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+            .with_filter(LevelFilter::INFO)
+    });
+    let stdout = logging::fmt_layer(&config.logging, std::io::stdout, std::io::stdout().is_terminal());
+    tracing_subscriber::registry()
+        .with(stdout.map(|layer| layer.with_filter(LevelFilter::INFO)))
+        .with(otel)
+        .try_init()?;
+    Ok(Telemetry { provider })
+}
+
+/// Batches the spans and posts them to `{otlp_endpoint}/v1/traces`.
+pub fn tracer_provider(config: &TelemetryConfig) -> anyhow::Result<SdkTracerProvider> {
+    let endpoint = config
+        .otlp_endpoint
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("No OTLP endpoint configured"))?;
+    let protocol = match config.otlp_protocol {
+        OtlpProtocol::HttpProtobuf => Protocol::HttpBinary,
+        OtlpProtocol::HttpJson => Protocol::HttpJson,
+    };
+    // Unlike `OTEL_EXPORTER_OTLP_ENDPOINT`, an endpoint given to the builder is
+    // used as is, so the signal path is added here
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(protocol)
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(config.service_name.clone()).build())
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Bytes, extract::State, routing::post};
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    use crate::config::ExecutorMode;
+    use crate::database::Database;
+    use crate::databases::SqliteDatabase;
+    use crate::metrics::Instrumented;
+
+    /// Stand-in for a collector, passing on the JSON bodies posted to `/v1/traces`.
+    async fn collector() -> (String, mpsc::UnboundedReceiver<Value>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/v1/traces",
+                post(|State(sender): State<mpsc::UnboundedSender<Value>>, body: Bytes| async move {
+                    sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                }),
+            )
+            .with_state(sender);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/", address), receiver)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_database_spans_are_exported() {
+        let (endpoint, mut received) = collector().await;
+        let mut config = Config::for_tests();
+        config.telemetry.otlp_endpoint = Some(endpoint);
+        config.telemetry.otlp_protocol = OtlpProtocol::HttpJson;
+        // The subscriber below only applies to this thread, which `Inline` stays on
+        config.executor.mode = ExecutorMode::Inline;
+
+        let provider = tracer_provider(&config.telemetry).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let db = Instrumented::<SqliteDatabase>::init(&config).await.unwrap();
+        assert!(db.get_user("missing".to_string()).await.is_err());
+        tokio::task::block_in_place(|| provider.force_flush()).unwrap();
+
+        let body = tokio::time::timeout(Duration::from_secs(10), received.recv()).await.unwrap().unwrap();
+        let resource = &body["resourceSpans"][0];
+        let service = &resource["resource"]["attributes"];
+        assert!(service.as_array().unwrap().contains(&attribute("service.name", "axum-db-benchmark")));
+
+        let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        let span = |name: &str| spans.iter().find(|span| span["name"] == name).unwrap();
+        let method = span("get_user");
+        assert_eq!(method["kind"], 3, "client span");
+        assert_eq!(method["status"]["code"], 2, "error status");
+        for expected in [
+            attribute("db.system", "sqlite"),
+            attribute("db.operation", "get_user"),
+            attribute("error.type", "not_found"),
+        ] {
+            assert!(method["attributes"].as_array().unwrap().contains(&expected), "{}", expected);
+        }
+        // Both the checkout and the query nest under the method
+        for child in ["pool.acquire", "db.query"] {
+            assert_eq!(span(child)["parentSpanId"], method["spanId"]);
+        }
+    }
+
+    fn attribute(key: &str, value: &str) -> Value {
+        serde_json::json!({ "key": key, "value": { "stringValue": value } })
+    }
+}